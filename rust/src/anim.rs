@@ -0,0 +1,49 @@
+#![allow(dead_code)]
+
+use clap::Parser;
+
+use anim_ctrl::anim_main;
+use animations::Animation;
+use display::DisplayKind;
+
+mod anim_ctrl;
+mod animations;
+mod blocks;
+mod config;
+mod constants;
+mod display;
+mod gradient;
+mod render_block;
+mod sim_display;
+mod var_types;
+
+/* Runs one of the hand-written animations, without the block renderer or server */
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct AnimArgs {
+    #[arg(short, long, default_value_t = 0)]
+    frame_cnt: u32,
+
+    /// Target frame rate, 0 to render as fast as the display allows
+    #[arg(long, default_value_t = 0.0)]
+    fps: f32,
+
+    #[arg(long, default_value_t = DisplayKind::Fpga)]
+    display: DisplayKind,
+
+    /// Frame rate of the simulated display
+    #[arg(long, default_value_t = 60.0)]
+    sim_fps: f32,
+
+    #[command(subcommand)]
+    animation: Animation,
+}
+
+fn main() {
+    let args = AnimArgs::parse();
+
+    let disp = args.display.open(args.sim_fps);
+    let mut anim = args.animation.open();
+
+    anim_main(disp.as_ref(), anim.as_mut(), args.frame_cnt, args.fps);
+}
@@ -0,0 +1,35 @@
+use std::time::{Duration, Instant};
+
+use crate::animations::common::Renderable;
+use crate::display::{blank_display, print_loop_stats, start_display, DisplayBackend, FramePacer};
+
+/*
+ * Renders an animation straight into the framebuffer, one frame per flush
+ * unless a frame rate is given. Stops after frame_cnt frames, or never if 0.
+ */
+pub fn anim_main(disp: &dyn DisplayBackend, anim: &mut dyn Renderable, frame_cnt: u32, fps: f32) {
+    start_display(disp);
+
+    let now = Instant::now();
+    let mut render_time = Duration::ZERO;
+    let mut pacer = FramePacer::new(fps);
+
+    let mut frame: u32 = 0;
+    while frame_cnt == 0 || frame < frame_cnt {
+        let render_start = Instant::now();
+        // The framebuffer can't be borrowed across a flush
+        anim.render(frame, &mut disp.borrow_fb());
+        render_time += render_start.elapsed();
+
+        // Call ioctl to DMA to hardware
+        disp.flush();
+
+        pacer.wait();
+
+        frame += 1;
+    }
+
+    print_loop_stats(disp, frame, now, render_time, pacer.pace_time);
+
+    blank_display(disp);
+}
@@ -1,4 +1,30 @@
+use clap::Subcommand;
+
+use common::Renderable;
+use waves::{WaveArgs, Waves};
+
 pub mod common;
 
+#[cfg(feature = "strobe")]
 pub mod strobe;
-pub mod waves;
\ No newline at end of file
+pub mod waves;
+
+/* The animations that can be run by name */
+#[derive(Subcommand)]
+pub enum Animation {
+    /// Interfering waves of color that drift about at random
+    Waves(WaveArgs),
+    /// Cycles the whole ceiling through colors, drawn with ImageMagick
+    #[cfg(feature = "strobe")]
+    Strobe,
+}
+
+impl Animation {
+    pub fn open(self) -> Box<dyn Renderable> {
+        match self {
+            Animation::Waves(args) => Box::new(Waves::new(args)),
+            #[cfg(feature = "strobe")]
+            Animation::Strobe => Box::new(strobe::Strobe::new()),
+        }
+    }
+}
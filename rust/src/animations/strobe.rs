@@ -1,5 +1,5 @@
 use std::cell::{RefMut};
-use magick_rust::{DrawingWand, PixelWand, MagickWand};
+use magick_rust::{magick_wand_genesis, DrawingWand, PixelWand, MagickWand};
 
 use crate::constants;
 use crate::animations::common::Renderable;
@@ -12,6 +12,8 @@ pub struct Strobe {
 
 impl Strobe {
     pub fn new() -> Self {
+        magick_wand_genesis();
+
         let wand = MagickWand::new();
         let draw = DrawingWand::new();
         let mut bg = PixelWand::new();
@@ -23,8 +25,8 @@ impl Strobe {
     fn prepare(&mut self) {
         // Create new image. There isn't a good way to clear the existing image, so just create a new one.
         self.wand.new_image(
-            constants::STRING_COUNT as usize,
-            constants::LED_COUNT as usize,
+            constants::STRING_COUNT,
+            constants::LED_COUNT,
             &self.bg)
             .expect("Could create new image");
 
@@ -39,11 +41,17 @@ impl Strobe {
     }
 }
 
+impl Default for Strobe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Renderable for Strobe {
     fn render(&mut self, frame: u32, fb: &mut RefMut<[u8]>) {
         self.prepare();
 
-        let r = (frame + 0) % 24;
+        let r = frame % 24;
         let b = (frame + 8) % 24;
         let g = (frame + 16) % 24;
         let color_str = format!("rgb({},{},{})", r, b, g);
@@ -58,8 +66,8 @@ impl Renderable for Strobe {
 
         // Export pixels in framebuffer format
         let img_data = self.wand.export_image_pixels(0, 0, 
-            constants::STRING_COUNT as usize, 
-            constants::LED_COUNT as usize, "BRG")
+            constants::STRING_COUNT, 
+            constants::LED_COUNT, "BRG")
             .expect("Could not export pixels");
 
         // Copy to kernel buffer
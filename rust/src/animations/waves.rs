@@ -1,13 +1,13 @@
 //use rand::prelude::*;
 use fastrand;
 use std::cell::{RefMut};
-use std::fmt;
-use interpolation::Lerp;
 
 use clap::{ValueEnum, Args};
 
 use crate::constants;
 use crate::animations::common::Renderable;
+use crate::blocks::wander::WanderN;
+use crate::gradient::{self, Mode, Space, Stop};
 
 #[derive(Args)]
 pub struct WaveArgs {
@@ -45,154 +45,25 @@ impl std::fmt::Display for ColorMap {
     }
 }
 
-pub struct ColorPoint {
-    val: f32,
-    color: [u8; 3],
-}
-
-struct Wander {
-    accel_to_ctr: f32,
-    temp: f32,
-    decel: f32,
-
-    bound_ctr: f32,
-    bound_radius: f32,
-
-    // Position and velocity are always for a 2x2 box centered at the origin
-    pos: f32,
-    vel: f32,
-    accel: f32,
-}
-
 fn rand_range(min: f32, max: f32) -> f32 {
     fastrand::f32() * (max-min) + min
 }
 
-impl Wander {
-    fn new(min: f32, max: f32, val: f32) -> Self {
-        let radius = (max - min)/2.0;
-        let center = (max + min)/2.0;
-        let pos = (val - center)/radius;
-
-        Self {
-            accel_to_ctr: 0.01,
-            temp: 0.003,
-            decel: 0.9,
-            bound_ctr: center,
-            bound_radius: radius,
-            pos,
-            vel: 0.0,
-            accel: 0.0,
-        }
-    }
-
-    fn step_accel(&mut self) {
-        /*
-         * Velocity change consists of:
-         * - deceleration towards stopped
-         * - acceleration towards center
-         * - random impulse
-         */
-        self.accel = self.decel * self.accel + rand_range(-self.temp, self.temp);
-    }
-
-    fn step(&mut self) {
-        self.vel =
-            -self.accel_to_ctr * self.pos.powi(5) +
-            self.accel;
-
-        self.pos += self.vel;
-    }
-
-    fn get_pos(&self) -> f32 {
-        self.bound_radius * self.pos + self.bound_ctr
-    }
-}
-
-impl fmt::Display for Wander {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} -> {}", self.pos, self.get_pos())
-    }
-}
-
-struct WanderN {
-    wanderers : Vec<Wander>
-}
-
-impl WanderN {
-    fn new(dim : usize, min: &[f32], max: &[f32], val: &[f32]) -> Self {
-        let mut wanderers = Vec::<Wander>::new();
-
-        for i in 0..dim {
-            wanderers.push(Wander::new(min[i], max[i], val[i]));
-        }
-
-        WanderN {wanderers}
-    }
-
-    fn step_accel(&mut self) {
-        self.wanderers.iter_mut().for_each(|w| w.step_accel());
-    }
-
-    fn step(&mut self) {
-        self.wanderers.iter_mut().for_each(|w| w.step());
-    }
-
-    fn get_pos(&self) -> Vec<f32> {
-        self.wanderers.iter().map(|w| w.get_pos()).collect()
-    }
-}
-
-impl fmt::Display for WanderN {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let s: Vec<String> = self.wanderers.iter().map(|w| w.to_string()).collect();
-        write!(f, "{}", s.join(", "))
-    }
-}
-
 pub struct Waves {
-    color_map: Box<[ColorPoint]>,
+    color_map: &'static [Stop],
     phase_coeffs: WanderN,
     phase_offsets: WanderN,
+    rng: fastrand::Rng,
 }
 
 impl Waves {
     pub fn new(args: WaveArgs) -> Self {
-        // Color format is framebuffer native, [B, R, G]
-        let color_map: Box<[ColorPoint]>;
-
-        match args.color_map {
-            ColorMap::Rainbow => {
-                //color_map = Box::new([
-                //    ColorPoint {val:-1.00001, color: [16,  0,  0] },
-                //    ColorPoint {val:-0.33333, color: [ 0, 16,  0] },
-                //    ColorPoint {val: 0.33333, color: [ 0,  0, 16] },
-                //    ColorPoint {val: 1.00001, color: [16,  0,  0] },
-                //    ]);
-                color_map = Box::new([
-                    ColorPoint {val:-1.00001, color: [16,  4,  4] },
-                    ColorPoint {val:-0.33333, color: [ 4, 16,  4] },
-                    ColorPoint {val: 0.33333, color: [ 4,  4, 16] },
-                    ColorPoint {val: 1.00001, color: [16,  4,  4] },
-                    ]);
-            }
-            ColorMap::Elite => {
-                color_map = Box::new([
-                    ColorPoint {val:-1.00001, color: [ 0,  0,  0] },
-                    ColorPoint {val: 0.20000, color: [ 0,  0,  0] },
-                    ColorPoint {val: 1.00001, color: [ 0, 24,  4] },
-                ]);
-            }
-            ColorMap::Sky => {
-                color_map = Box::new([
-                    ColorPoint {val:-1.00001, color: [24,  0,  0] },
-                    ColorPoint {val:-0.80000, color: [24,  0,  0] },
-                    ColorPoint {val:-0.40000, color: [16, 16, 16] },
-                    ColorPoint {val: 0.50000, color: [ 4,  4,  4] },
-                    ColorPoint {val: 1.00001, color: [ 1,  1,  1] },
-                ]);
-            }
-        }
+        let name = match args.color_map {
+            ColorMap::Rainbow => "rainbow",
+            ColorMap::Elite => "elite",
+            ColorMap::Sky => "sky",
+        };
+        let color_map = gradient::builtin(name).expect("every ColorMap is built in");
 
         let phase_coeff_start = [args.frame_coeff, args.x_coeff, args.y_coeff, args.r_coeff];
         let phase_coeffs = WanderN::new(4, &[-0.3; 4], &[0.3; 4], &phase_coeff_start);
@@ -200,22 +71,22 @@ impl Waves {
         let phase_offset_start = [rand_range(0.0, constants::LED_COUNT as f32), rand_range(0.0, constants::STRING_COUNT as f32)];
         let phase_offsets = WanderN::new(2, &[0.0; 2], &[constants::LED_COUNT as f32, constants::STRING_COUNT as f32], &phase_offset_start);
 
-        Self {color_map, phase_coeffs, phase_offsets}
+        Self {color_map, phase_coeffs, phase_offsets, rng: fastrand::Rng::new()}
     }
 }
 
 fn dot(a: &[f32], b: &[f32]) -> f32 {
-    return a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
 }
 
 impl Renderable for Waves {
     fn render(&mut self, frame: u32, fb: &mut RefMut<[u8]>) {
         let frame_f32 = frame as f32;
-        if frame % 20 == 0 {
+        if frame.is_multiple_of(20) {
             //print!("Offsets: {}\n", self.phase_offsets);
             //print!("Coeffs: {}\n", self.phase_coeffs);
-            self.phase_offsets.step_accel();
-            self.phase_coeffs.step_accel();
+            self.phase_offsets.step_accel(&mut self.rng);
+            self.phase_coeffs.step_accel(&mut self.rng);
         }
         self.phase_offsets.step();
         self.phase_coeffs.step();
@@ -243,7 +114,7 @@ impl Renderable for Waves {
             for (y, p) in row.iter_mut().enumerate() {
                 let v = p.rem_euclid(2.0) - 1.0;
                 if v < -1.0 {
-                    print!("{x},{y} {p} {v}\n");
+                    println!("{x},{y} {p} {v}");
                 }
                 *p = v;
             }
@@ -261,20 +132,13 @@ impl Renderable for Waves {
 
         for (x, row) in color_index.iter_mut().enumerate() {
             for (y, p) in row.iter_mut().enumerate() {
-                // Find which pair of points this value falls between
-                //print!("{x},{y} {p}\n");
-                let i = self.color_map.iter().position(|x| *p <= x.val).expect("{p} is outside of map limits") - 1;
-
-                // Scale the independent variable
-                //print!("Using index {i}, original value is {p}\n");
-                let alpha: f32 = (*p - self.color_map[i].val)/(self.color_map[i+1].val - self.color_map[i].val);
-                // Perform the interpolation
-                //print!("alpha = {alpha}\n");
-                let color = self.color_map[i].color.lerp(&self.color_map[i+1].color, &alpha);
+                // The maps run from 0 to 1
+                let color = gradient::sample(self.color_map, (*p + 1.0) / 2.0, Mode::Clamp, Space::Rgb);
+                let color = [color.b, color.r, color.g].map(|c| (c * 255.0).round() as u8);
 
                 //print!("{x},{y} {p} -> {color:?}\n");
                 let idx = constants::fb_idx(x, y);
-                fb[idx + 0] = color[0];
+                fb[idx] = color[0];
                 fb[idx + 1] = color[1];
                 fb[idx + 2] = color[2];
             }
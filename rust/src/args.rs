@@ -1,5 +1,8 @@
 use clap::Parser;
 
+use crate::display::DisplayKind;
+use crate::dmx::{DmxProtocol, MAX_FIRST_UNIVERSE};
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
@@ -11,4 +14,99 @@ pub struct Args {
 
     #[arg(short, long, default_value_t = false)]
     pub debug: bool,
+
+    /// Target frame rate, 0 to render as fast as the display allows
+    #[arg(long, default_value_t = 0.0)]
+    pub fps: f32,
+
+    /// Schedule of configs to cycle through, see configs/playlist.json
+    #[arg(long)]
+    pub playlist: Option<String>,
+
+    /// Drive scalars from DMX, mapped by --dmx-map
+    #[arg(long, requires = "dmx_map")]
+    pub dmx: Option<DmxProtocol>,
+
+    /// Which DMX channels drive which scalars, see configs/dmx_map.json
+    #[arg(long)]
+    pub dmx_map: Option<String>,
+
+    /// Number of render threads, 0 for one per core
+    #[arg(short, long, default_value_t = 0)]
+    pub threads: usize,
+
+    #[arg(long, default_value_t = DisplayKind::Fpga)]
+    pub display: DisplayKind,
+
+    /// Frame rate of the simulated display
+    #[arg(long, default_value_t = 60.0)]
+    pub sim_fps: f32,
+
+    /// Stream flushed frames to a browser at /preview
+    #[arg(long, default_value_t = false)]
+    pub preview: bool,
+}
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+pub struct MovieArgs {
+    /// Take frames from DMX instead of /set_data
+    #[arg(long)]
+    pub dmx: Option<DmxProtocol>,
+
+    /// First of the consecutive universes that make up a frame
+    #[arg(long, default_value_t = 1,
+          value_parser = clap::value_parser!(u16).range(..=i64::from(MAX_FIRST_UNIVERSE)))]
+    pub dmx_universe: u16,
+
+    /// Accept Open Pixel Control over TCP, optionally on another port
+    #[arg(long, num_args = 0..=1, default_missing_value = "7890")]
+    pub opc: Option<u16>,
+
+    /// Accept DDP over UDP, optionally on another port
+    #[arg(long, num_args = 0..=1, default_missing_value = "4048")]
+    pub ddp: Option<u16>,
+
+    /// Directory that --play and /player/open files are looked up in
+    #[arg(long, default_value_t = String::from("."))]
+    pub media_dir: String,
+
+    /// Play a GIF, APNG or raw RGB file at startup
+    #[arg(long)]
+    pub play: Option<String>,
+
+    /// Loop the file given with --play
+    #[arg(long = "loop", default_value_t = false)]
+    pub looping: bool,
+
+    /// Playback speed, 1.0 being the file's own frame rate
+    #[arg(long, default_value_t = 1.0)]
+    pub rate: f32,
+
+    /// Treat --play as raw RGB frames of this size, e.g. 320x180
+    #[arg(long, value_parser = parse_size)]
+    pub raw: Option<(usize, usize)>,
+
+    /// Frame rate of a raw file
+    #[arg(long, default_value_t = 30.0)]
+    pub raw_fps: f32,
+
+    #[arg(long, default_value_t = DisplayKind::Fpga)]
+    pub display: DisplayKind,
+
+    /// Frame rate of the simulated display
+    #[arg(long, default_value_t = 60.0)]
+    pub sim_fps: f32,
+
+    /// Stream flushed frames to a browser at /preview
+    #[arg(long, default_value_t = false)]
+    pub preview: bool,
+}
+
+/* Parses WIDTHxHEIGHT */
+fn parse_size(s: &str) -> Result<(usize, usize), String> {
+    let (w, h) = s.split_once('x').ok_or("expected WIDTHxHEIGHT")?;
+    let w = w.parse().map_err(|_| format!("bad width {w}"))?;
+    let h = h.parse().map_err(|_| format!("bad height {h}"))?;
+    Ok((w, h))
 }
@@ -1,6 +1,8 @@
-use crate::render_block::{RenderBlock, RenderState};
-use json::JsonValue;
+use crate::config::{ConfigError, ConfigObj};
+use crate::render_block::{Port, Purity, RenderBlock, RenderState};
+use crate::var_types::VarType;
 
+#[derive(Clone)]
 pub struct ColorInterp {
     // Inputs
     color_idxs: Vec<usize>,
@@ -13,83 +15,85 @@ pub struct ColorInterp {
 }
 
 impl ColorInterp {
-    pub fn from_obj(dict: &json::object::Object) -> Self {
-        let input_obj = match dict.get("inputs").expect("Missing input definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ColorInterp inputs is not an object"),
-        };
-
-        let color_array = match input_obj.get("color").expect("Missing color inputs") {
-            JsonValue::Array(x) => x,
-            _ => panic!("Color input must be array"),
-        };
-
-        let mut color_idxs = Vec::<usize>::with_capacity(color_array.len());
-        for c in color_array {
-            color_idxs.push(c.as_usize().expect("Could not parse color index"));
-        }
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        let input_obj = dict.obj("inputs")?;
 
-        let point_array = match input_obj.get("point").expect("Missing color inputs") {
-            JsonValue::Array(x) => x,
-            _ => panic!("Color input must be array"),
-        };
+        let color_idxs = input_obj.index_array("color", VarType::Color)?;
+        let point_idxs = input_obj.index_array("point", VarType::Scalar)?;
 
-        let mut point_idxs = Vec::<usize>::with_capacity(point_array.len());
-        for p in point_array {
-            point_idxs.push(p.as_usize().expect("Could not parse point index"));
+        if color_idxs.len() != point_idxs.len() {
+            return Err(input_obj.err("point", "color and point inputs must be the same length"));
+        }
+        if point_idxs.len() < 2 {
+            return Err(input_obj.err("point", "at least two points are required"));
         }
 
-        let val_idx = input_obj
-            .get("val")
-            .expect("Missing value input")
-            .as_usize()
-            .expect("Could not parse value input");
-
-        let outputs = dict.get("outputs").expect("Missing input definition");
-        let output_obj = match outputs {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ColorInterp outputs is not an object"),
-        };
-
-        let o_idx = output_obj
-            .get("o")
-            .expect("Missing o output")
-            .as_usize()
-            .expect("Could not parse o output");
-
-        return ColorInterp {
+        let val_idx = input_obj.index("val", VarType::Scalar)?;
+
+        let output_obj = dict.obj("outputs")?;
+
+        let o_idx = output_obj.index("o", VarType::Color)?;
+
+        Ok(ColorInterp {
             color_idxs,
             point_idxs,
             val_idx,
             o_idx,
-        };
+        })
     }
 }
 
 impl RenderBlock for ColorInterp {
     fn execute(&mut self, state: &mut RenderState) {
-        // Get our current value
-        let val = state.get_scalar(self.val_idx);
+        // Get our current value, held to the ends of the map. max and min
+        // rather than clamp, which panics if a point is NaN.
+        let first = state.get_scalar(self.point_idxs[0]);
+        let last = state.get_scalar(self.point_idxs[self.point_idxs.len() - 1]);
+        let val = state
+            .get_scalar(self.val_idx)
+            .max(first)
+            .min(last.max(first));
         // Find which pair of points this value falls between
         let i = self
             .point_idxs
             .iter()
             .position(|x| val <= state.get_scalar(*x))
-            .expect("{val} is outside of map limits")
+            .unwrap_or(self.point_idxs.len() - 1)
+            .max(1)
             - 1;
         //print!("Using index {i}, original value is {val}\n");
 
         // Scale the independent variable
-        let start_val = state.get_scalar(i);
-        let end_val = state.get_scalar(i + 1);
-        let alpha = (val - start_val) / (end_val - start_val);
+        let start_val = state.get_scalar(self.point_idxs[i]);
+        let end_val = state.get_scalar(self.point_idxs[i + 1]);
+        let alpha = ((val - start_val) / (end_val - start_val)).clamp(0.0, 1.0);
         //print!("alpha = {alpha}\n");
 
         // Perform the interpolation
-        let start_color = state.get_color(i);
-        let end_color = state.get_color(i + 1);
+        let start_color = state.get_color(self.color_idxs[i]);
+        let end_color = state.get_color(self.color_idxs[i + 1]);
         let color = (*start_color * (1.0 - alpha)) + (*end_color * alpha);
 
         state.set_color(self.o_idx, color);
     }
+
+    fn inputs(&self) -> Vec<Port> {
+        let mut ports: Vec<Port> = self
+            .color_idxs
+            .iter()
+            .enumerate()
+            .map(|(i, idx)| Port::new(format!("color[{i}]"), VarType::Color, *idx))
+            .collect();
+        ports.extend(Port::scalars("point", &self.point_idxs));
+        ports.push(Port::scalar("val", self.val_idx));
+        ports
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        vec![Port::new("o", VarType::Color, self.o_idx)]
+    }
+
+    fn purity(&self) -> Purity {
+        Purity::Pure
+    }
 }
@@ -1,10 +1,140 @@
-use json::JsonValue;
 use num_traits::{clamp, Pow};
 //use rand::Rng;
 
-use crate::render_block::{RenderBlock, RenderState};
-use crate::var_types::Color;
+use crate::config::{ConfigError, ConfigObj};
+use crate::render_block::{
+    pixel_at, PixelBuffer, Port, Purity, RenderBlock, RenderState, SCALAR_FRAME,
+};
+use crate::var_types::{Color, RealColor, VarType};
 
+/* How levels between two 8-bit steps are spread out */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DitherMode {
+    /* A fixed pattern of offsets over x, y and the frame */
+    Ordered,
+    /* Error diffusion in time */
+    Temporal,
+    /* Error diffusion in time and space */
+    FloydSteinberg,
+}
+
+impl DitherMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ordered" => Some(DitherMode::Ordered),
+            "temporal" => Some(DitherMode::Temporal),
+            "floyd_steinberg" => Some(DitherMode::FloydSteinberg),
+            _ => None,
+        }
+    }
+}
+
+/*
+ * Floyd-Steinberg weights for the pixels rendered after (x, y): the next one
+ * along the string and three in the next column.
+ */
+const FS_WEIGHTS: [(usize, isize, f32); 4] = [
+    (0, 1, 7.0 / 16.0),
+    (1, -1, 3.0 / 16.0),
+    (1, 0, 5.0 / 16.0),
+    (1, 1, 1.0 / 16.0),
+];
+
+/*
+ * What each pixel lost to rounding, carried forward instead of thrown away so
+ * that a level between two steps comes out right on average. Without spatial
+ * diffusion all of it goes to the same pixel on the next frame. With it, the
+ * error is spread over FS_WEIGHTS and only the shares that would land off the
+ * grid wait for the next frame.
+ *
+ * Spatial diffusion relies on pixels being quantized in px_idx order.
+ */
+#[derive(Debug, Clone)]
+pub struct Residual {
+    // In 8-bit steps per channel. Not a RealColor since its ops clamp to 0-1.
+    error: PixelBuffer<[f32; 3]>,
+}
+
+impl Residual {
+    pub fn new() -> Self {
+        Residual {
+            error: PixelBuffer::new(),
+        }
+    }
+
+    /* Rounds a level plus the error carried to it, returning the new error */
+    fn split(level: RealColor, carried: [f32; 3]) -> (Color, [f32; 3]) {
+        let mut q = [0u8; 3];
+        let mut err = [0.0f32; 3];
+        for (c, v) in [level.r, level.g, level.b].into_iter().enumerate() {
+            let target = 255.0 * v + carried[c];
+            let target = if target.is_finite() { target } else { 0.0 };
+            let rounded = target.round().clamp(0.0, 255.0);
+            q[c] = rounded as u8;
+            // Clipped levels would otherwise build up error without bound
+            err[c] = (target - rounded).clamp(-0.5, 0.5);
+        }
+
+        let color = Color {
+            r: q[0],
+            g: q[1],
+            b: q[2],
+        };
+        (color, err)
+    }
+
+    /* Rounds a level, 0 to 1 per channel, to 8 bits with nothing carried */
+    pub fn round(level: RealColor) -> Color {
+        Self::split(level, [0.0; 3]).0
+    }
+
+    /* The same for pixel (x, y), diffusing the error */
+    pub fn quantize(&mut self, x: usize, y: usize, level: RealColor, spatial: bool) -> Color {
+        let carried = self
+            .error
+            .get_mut(x, y)
+            .map(std::mem::take)
+            .unwrap_or_default();
+        let (color, err) = Self::split(level, carried);
+
+        let mut kept = 1.0;
+        if spatial {
+            for (dx, dy, w) in FS_WEIGHTS {
+                let Some(ny) = y.checked_add_signed(dy) else {
+                    continue;
+                };
+                if let Some(slot) = self.error.get_mut(x + dx, ny) {
+                    for c in 0..3 {
+                        slot[c] += err[c] * w;
+                    }
+                    kept -= w;
+                }
+            }
+        }
+        if let Some(slot) = self.error.get_mut(x, y) {
+            for c in 0..3 {
+                slot[c] += err[c] * kept;
+            }
+        }
+
+        color
+    }
+}
+
+/*
+ * Converts a perceptual rcolor to an 8-bit color through a gamma curve and
+ * per-channel gains, e.g.
+ *   {"type": "dither", "params": {"gamma": 2.4, "rc": 1.5, "gc": 0.88,
+ *    "bc": 0.47, "mode": "temporal"}, "inputs": {"i": 0, "x": 1, "y": 2}, ...}
+ *
+ * "mode" is one of DitherMode: ordered (the default), temporal or
+ * floyd_steinberg. The error diffusion modes keep a residual per pixel, looked
+ * up by the x and y inputs. That makes the block stateful, so the whole config
+ * renders on one thread. The "dither" option of the output stage (see
+ * OutputStage) does the same after the render threads are done, so configs
+ * that can use it should.
+ */
+#[derive(Clone)]
 pub struct Dither {
     // Params
     dither_add: Box<[f32]>,
@@ -13,6 +143,9 @@ pub struct Dither {
     rc: f32,
     gc: f32,
     bc: f32,
+    // None for ordered dithering
+    residual: Option<Residual>,
+    spatial: bool,
 
     // Inputs
     i_idx: usize,
@@ -25,63 +158,35 @@ pub struct Dither {
 }
 
 impl Dither {
-    pub fn from_obj(dict: &json::object::Object) -> Self {
-        let input_obj = match dict.get("params").expect("Missing params definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for Dither params is not an object"),
-        };
-        let gamma = input_obj
-            .get("gamma")
-            .expect("Missing gamma input")
-            .as_f32()
-            .expect("Could not parse gamma input");
-        let rc = input_obj
-            .get("rc")
-            .expect("Missing R coefficient input")
-            .as_f32()
-            .expect("Could not parse R coefficient input");
-        let gc = input_obj
-            .get("gc")
-            .expect("Missing G coefficient input")
-            .as_f32()
-            .expect("Could not parse G coefficient input");
-        let bc = input_obj
-            .get("bc")
-            .expect("Missing B coefficient input")
-            .as_f32()
-            .expect("Could not parse B coefficient input");
-
-        let input_obj = match dict.get("inputs").expect("Missing input definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for Dither inputs is not an object"),
-        };
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        let param_obj = dict.obj("params")?;
 
-        let i_idx = input_obj
-            .get("i")
-            .expect("Missing i input")
-            .as_usize()
-            .expect("Could not parse i input");
-        let x_idx = input_obj
-            .get("x")
-            .expect("Missing x input")
-            .as_usize()
-            .expect("Could not parse x input");
-        let y_idx = input_obj
-            .get("y")
-            .expect("Missing y input")
-            .as_usize()
-            .expect("Could not parse y input");
-
-        let output_obj = match dict.get("outputs").expect("Missing output definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for Dither outputs is not an object"),
-        };
+        let gamma = param_obj.f32("gamma")?;
+        let rc = param_obj.f32("rc")?;
+        let gc = param_obj.f32("gc")?;
+        let bc = param_obj.f32("bc")?;
+
+        let mut mode = DitherMode::Ordered;
+        if param_obj.opt("mode").is_some() {
+            let name = param_obj.str("mode")?;
+            mode = DitherMode::from_name(name).ok_or_else(|| {
+                param_obj.err(
+                    "mode",
+                    "expected 'ordered', 'temporal' or 'floyd_steinberg'",
+                )
+            })?;
+        }
+        let residual = (mode != DitherMode::Ordered).then(Residual::new);
+
+        let input_obj = dict.obj("inputs")?;
 
-        let o_idx = output_obj
-            .get("o")
-            .expect("Missing o output")
-            .as_usize()
-            .expect("Could not parse o output");
+        let i_idx = input_obj.index("i", VarType::RColor)?;
+        let x_idx = input_obj.index("x", VarType::Scalar)?;
+        let y_idx = input_obj.index("y", VarType::Scalar)?;
+
+        let output_obj = dict.obj("outputs")?;
+
+        let o_idx = output_obj.index("o", VarType::Color)?;
 
         /*
          * dither adders for period 2:
@@ -112,17 +217,19 @@ impl Dither {
             7.0 / 8.0,
         ]);
 
-        Dither {
+        Ok(Dither {
             dither_add,
             gamma,
             rc,
             gc,
             bc,
+            residual,
+            spatial: mode == DitherMode::FloydSteinberg,
             i_idx,
             x_idx,
             y_idx,
             o_idx,
-        }
+        })
     }
 }
 
@@ -131,10 +238,27 @@ impl RenderBlock for Dither {
         //let mut rng = rand::thread_rng();
         let rcolor = state.get_rcolor(self.i_idx);
 
+        if let Some(residual) = self.residual.as_mut() {
+            let level = RealColor {
+                r: self.rc * rcolor.r.pow(self.gamma),
+                g: self.gc * rcolor.g.pow(self.gamma),
+                b: self.bc * rcolor.b.pow(self.gamma),
+            };
+            let x = state.get_scalar(self.x_idx);
+            let y = state.get_scalar(self.y_idx);
+            let c = match pixel_at(x, y) {
+                Some((x, y)) => residual.quantize(x, y, level, self.spatial),
+                // Off the grid, so there is nowhere to keep the error
+                None => Residual::round(level),
+            };
+            state.set_color(self.o_idx, c);
+            return;
+        }
+
         // Phase is (x + y) % 8
         let dither_phase = (state.get_scalar(self.x_idx)
             + 5.0 * state.get_scalar(self.y_idx)
-            + 3.0 * state.get_scalar(0))
+            + 3.0 * state.get_scalar(SCALAR_FRAME))
         .round() as usize;
         let dither_phase = dither_phase.rem_euclid(self.dither_add.len());
         //let dither_phase: usize = rng.gen_range(0..8);
@@ -162,4 +286,97 @@ impl RenderBlock for Dither {
 
         state.set_color(self.o_idx, c);
     }
+
+    fn inputs(&self) -> Vec<Port> {
+        let mut ports = vec![
+            Port::new("i", VarType::RColor, self.i_idx),
+            Port::scalar("x", self.x_idx),
+            Port::scalar("y", self.y_idx),
+        ];
+        if self.residual.is_none() {
+            // Not configurable, the frame counter drives the dither phase
+            ports.push(Port::scalar("t", SCALAR_FRAME));
+        }
+        ports
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        vec![Port::new("o", VarType::Color, self.o_idx)]
+    }
+
+    fn purity(&self) -> Purity {
+        // The residual has to be kept up to date for every pixel
+        match self.residual {
+            Some(_) => Purity::Stateful,
+            None => Purity::Pure,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{LED_COUNT, STRING_COUNT};
+
+    /* In 8-bit steps, none of them on a step or near clipping */
+    const TARGET: [f32; 3] = [76.5, 0.25, 102.7];
+
+    fn level() -> RealColor {
+        RealColor {
+            r: TARGET[0] / 255.0,
+            g: TARGET[1] / 255.0,
+            b: TARGET[2] / 255.0,
+        }
+    }
+
+    #[test]
+    fn temporal_averages_to_the_level() {
+        const FRAMES: usize = 200;
+
+        let mut residual = Residual::new();
+        let mut sum = [0u32; 3];
+        for _ in 0..FRAMES {
+            let c = residual.quantize(5, 7, level(), false);
+            for (s, v) in sum.iter_mut().zip([c.r, c.g, c.b]) {
+                *s += v as u32;
+            }
+        }
+
+        // What's still carried is at most half a step
+        for (s, t) in sum.into_iter().zip(TARGET) {
+            let avg = s as f32 / FRAMES as f32;
+            assert!(
+                (avg - t).abs() <= 0.5 / FRAMES as f32 + 1e-4,
+                "{avg} vs {t}"
+            );
+        }
+    }
+
+    #[test]
+    fn floyd_steinberg_stays_on_the_grid() {
+        let mut residual = Residual::new();
+        let mut sum = [0.0f64; 3];
+        for x in 0..LED_COUNT {
+            for y in 0..STRING_COUNT {
+                let c = residual.quantize(x, y, level(), true);
+                for (s, v) in sum.iter_mut().zip([c.r, c.g, c.b]) {
+                    *s += v as f64;
+                }
+            }
+        }
+
+        // Error from the last column and string stays in the grid for the next frame
+        for x in 0..LED_COUNT {
+            for y in 0..STRING_COUNT {
+                let err = residual.error.get_mut(x, y).unwrap();
+                for (s, e) in sum.iter_mut().zip(*err) {
+                    *s += e as f64;
+                }
+            }
+        }
+        for (s, t) in sum.into_iter().zip(TARGET) {
+            let expected = t as f64 * (LED_COUNT * STRING_COUNT) as f64;
+            assert!((s - expected).abs() < 0.05, "{s} vs {expected}");
+        }
+    }
 }
@@ -0,0 +1,792 @@
+use std::collections::HashSet;
+
+use crate::config::{ConfigError, ConfigObj};
+use crate::render_block::{
+    Port, Purity, RenderBlock, RenderState, NAME_ELAPSED, SCALAR_X, SCALAR_Y,
+};
+use crate::var_types::VarType;
+
+/*
+ * Evaluates formulas over scalars, e.g.
+ *   {"type": "expr",
+ *    "params": {"expr": "sin(t*0.3 + hypot(x-cx, y-cy)*k)"},
+ *    "inputs": {"t": "elapsed", "cx": 4, "cy": 5, "k": "ripple"},
+ *    "outputs": {"o": "value"}}
+ *
+ * Identifiers are bound by the inputs, or failing that to the named float of
+ * the same name. Unnamed, `t` is the float named "elapsed" and `x` and `y`
+ * are the pixel scalars. Several outputs are given as
+ * "exprs": {"r": "...", "g": "..."}, evaluated in order, each written to the
+ * output of the same name.
+ *
+ * Each formula is compiled into a little stack program when the config loads.
+ * Operators are + - * / % and ^ for powers, with comparisons giving 1 or 0.
+ */
+#[derive(Clone)]
+pub struct Expr {
+    programs: Vec<Program>,
+
+    // Scratch space for evaluation, sized for the deepest program
+    stack: Vec<f32>,
+}
+
+#[derive(Clone)]
+struct Program {
+    ops: Vec<Op>,
+    /* Each identifier the formula reads and the slot it's bound to */
+    loads: Vec<(String, usize)>,
+
+    name: String,
+    o_idx: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Const(f32),
+    Load(usize),
+    Neg,
+    Bin(BinOp),
+    Call(Func),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl BinOp {
+    fn apply(self, a: f32, b: f32) -> f32 {
+        let truth = |c: bool| if c { 1.0 } else { 0.0 };
+        match self {
+            BinOp::Add => a + b,
+            BinOp::Sub => a - b,
+            BinOp::Mul => a * b,
+            BinOp::Div => a / b,
+            // Always non-negative, which is what wrapping phases want
+            BinOp::Rem => a.rem_euclid(b),
+            BinOp::Pow => a.powf(b),
+            BinOp::Lt => truth(a < b),
+            BinOp::Le => truth(a <= b),
+            BinOp::Gt => truth(a > b),
+            BinOp::Ge => truth(a >= b),
+            BinOp::Eq => truth(a == b),
+            BinOp::Ne => truth(a != b),
+        }
+    }
+
+    /* Binding power, higher binds tighter, and whether it's right associative */
+    fn precedence(self) -> (u8, bool) {
+        match self {
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::Eq | BinOp::Ne => (1, false),
+            BinOp::Add | BinOp::Sub => (2, false),
+            BinOp::Mul | BinOp::Div | BinOp::Rem => (3, false),
+            BinOp::Pow => (5, true),
+        }
+    }
+}
+
+/* Unary minus binds looser than ^, so -x^2 is -(x^2) */
+const NEG_PRECEDENCE: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Sqrt,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Fract,
+    Exp,
+    Ln,
+    Log2,
+    Log10,
+    Sign,
+    Min,
+    Max,
+    Pow,
+    Hypot,
+    Clamp,
+    Mix,
+    Step,
+    Smoothstep,
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "sin" => Func::Sin,
+            "cos" => Func::Cos,
+            "tan" => Func::Tan,
+            "asin" => Func::Asin,
+            "acos" => Func::Acos,
+            "atan" => Func::Atan,
+            "atan2" => Func::Atan2,
+            "sqrt" => Func::Sqrt,
+            "abs" => Func::Abs,
+            "floor" => Func::Floor,
+            "ceil" => Func::Ceil,
+            "round" => Func::Round,
+            "fract" => Func::Fract,
+            "exp" => Func::Exp,
+            "ln" => Func::Ln,
+            "log2" => Func::Log2,
+            "log10" => Func::Log10,
+            "sign" => Func::Sign,
+            "min" => Func::Min,
+            "max" => Func::Max,
+            "pow" => Func::Pow,
+            "hypot" => Func::Hypot,
+            "clamp" => Func::Clamp,
+            "mix" => Func::Mix,
+            "step" => Func::Step,
+            "smoothstep" => Func::Smoothstep,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Func::Atan2 | Func::Min | Func::Max | Func::Pow | Func::Hypot | Func::Step => 2,
+            Func::Clamp | Func::Mix | Func::Smoothstep => 3,
+            _ => 1,
+        }
+    }
+
+    /* `a` holds the arguments in order, unused ones are zero */
+    fn apply(self, a: [f32; 3]) -> f32 {
+        match self {
+            Func::Sin => a[0].sin(),
+            Func::Cos => a[0].cos(),
+            Func::Tan => a[0].tan(),
+            Func::Asin => a[0].asin(),
+            Func::Acos => a[0].acos(),
+            Func::Atan => a[0].atan(),
+            Func::Atan2 => a[0].atan2(a[1]),
+            Func::Sqrt => a[0].sqrt(),
+            Func::Abs => a[0].abs(),
+            Func::Floor => a[0].floor(),
+            Func::Ceil => a[0].ceil(),
+            Func::Round => a[0].round(),
+            Func::Fract => a[0].rem_euclid(1.0),
+            Func::Exp => a[0].exp(),
+            Func::Ln => a[0].ln(),
+            Func::Log2 => a[0].log2(),
+            Func::Log10 => a[0].log10(),
+            Func::Sign => {
+                if a[0] == 0.0 {
+                    0.0
+                } else {
+                    a[0].signum()
+                }
+            }
+            Func::Min => a[0].min(a[1]),
+            Func::Max => a[0].max(a[1]),
+            Func::Pow => a[0].powf(a[1]),
+            Func::Hypot => a[0].hypot(a[1]),
+            Func::Clamp => a[0].max(a[1]).min(a[2]),
+            Func::Mix => a[0] + (a[1] - a[0]) * a[2],
+            Func::Step => {
+                if a[1] < a[0] {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Func::Smoothstep => {
+                let t = ((a[2] - a[0]) / (a[1] - a[0])).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f32),
+    Ident(String),
+    Op(BinOp),
+    Minus,
+    Plus,
+    LParen,
+    RParen,
+    Comma,
+}
+
+/* Splits a formula into tokens, each with the column it starts at */
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, (usize, String)> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c.is_ascii_digit() || c == '.' {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // An exponent, e.g. 1e-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let s: String = chars[start..i].iter().collect();
+            let n = s
+                .parse()
+                .map_err(|_| (start, format!("bad number '{s}'")))?;
+            tokens.push((Token::Num(n), start));
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), start));
+            continue;
+        }
+
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            ('<', Some('=')) => (Token::Op(BinOp::Le), 2),
+            ('>', Some('=')) => (Token::Op(BinOp::Ge), 2),
+            ('=', Some('=')) => (Token::Op(BinOp::Eq), 2),
+            ('!', Some('=')) => (Token::Op(BinOp::Ne), 2),
+            ('<', _) => (Token::Op(BinOp::Lt), 1),
+            ('>', _) => (Token::Op(BinOp::Gt), 1),
+            ('*', _) => (Token::Op(BinOp::Mul), 1),
+            ('/', _) => (Token::Op(BinOp::Div), 1),
+            ('%', _) => (Token::Op(BinOp::Rem), 1),
+            ('^', _) => (Token::Op(BinOp::Pow), 1),
+            ('+', _) => (Token::Plus, 1),
+            ('-', _) => (Token::Minus, 1),
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            (',', _) => (Token::Comma, 1),
+            _ => return Err((start, format!("unexpected '{c}'"))),
+        };
+        tokens.push((token, start));
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+/*
+ * A precedence climbing parser that emits ops as it goes. `bind` looks up
+ * the slot for an identifier that isn't a function or constant.
+ */
+struct Parser<'a, F: Fn(&str) -> Option<usize>> {
+    tokens: &'a [(Token, usize)],
+    pos: usize,
+    end: usize,
+    bind: F,
+    ops: Vec<Op>,
+    loads: Vec<(String, usize)>,
+}
+
+impl<F: Fn(&str) -> Option<usize>> Parser<'_, F> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    /* The column of the next token, or the end of the formula */
+    fn col(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(_, c)| *c)
+    }
+
+    fn expect(&mut self, want: Token, what: &str) -> Result<(), (usize, String)> {
+        if self.peek() == Some(&want) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err((self.col(), format!("expected {what}")))
+        }
+    }
+
+    fn binary_op(&self) -> Option<BinOp> {
+        match self.peek()? {
+            Token::Op(op) => Some(*op),
+            Token::Plus => Some(BinOp::Add),
+            Token::Minus => Some(BinOp::Sub),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self, min_prec: u8) -> Result<(), (usize, String)> {
+        self.unary()?;
+
+        while let Some(op) = self.binary_op() {
+            let (prec, right) = op.precedence();
+            if prec < min_prec {
+                break;
+            }
+            self.pos += 1;
+            self.expr(if right { prec } else { prec + 1 })?;
+            self.ops.push(Op::Bin(op));
+        }
+
+        Ok(())
+    }
+
+    fn unary(&mut self) -> Result<(), (usize, String)> {
+        match self.peek() {
+            Some(Token::Minus) => {
+                self.pos += 1;
+                self.expr(NEG_PRECEDENCE)?;
+                self.ops.push(Op::Neg);
+                Ok(())
+            }
+            Some(Token::Plus) => {
+                self.pos += 1;
+                self.expr(NEG_PRECEDENCE)
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<(), (usize, String)> {
+        let col = self.col();
+        let token = self.peek().cloned();
+        self.pos += 1;
+
+        match token {
+            Some(Token::Num(n)) => self.ops.push(Op::Const(n)),
+            Some(Token::LParen) => {
+                self.expr(0)?;
+                self.expect(Token::RParen, "')'")?;
+            }
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::LParen) => {
+                let func = Func::from_name(&name)
+                    .ok_or_else(|| (col, format!("unknown function '{name}'")))?;
+                self.pos += 1;
+
+                for i in 0..func.arity() {
+                    if i > 0 {
+                        self.expect(Token::Comma, "','")?;
+                    }
+                    self.expr(0)?;
+                }
+                self.expect(
+                    Token::RParen,
+                    &format!("')', {name} takes {} argument(s)", func.arity()),
+                )?;
+                self.ops.push(Op::Call(func));
+            }
+            Some(Token::Ident(name)) => {
+                let op = match (self.bind)(&name) {
+                    Some(idx) => {
+                        if !self.loads.iter().any(|(n, _)| *n == name) {
+                            self.loads.push((name, idx));
+                        }
+                        Op::Load(idx)
+                    }
+                    None => match name.as_str() {
+                        "pi" => Op::Const(std::f32::consts::PI),
+                        "tau" => Op::Const(std::f32::consts::TAU),
+                        "e" => Op::Const(std::f32::consts::E),
+                        _ => return Err((col, format!("'{name}' is not an input or named float"))),
+                    },
+                };
+                self.ops.push(op);
+            }
+            _ => {
+                self.pos -= 1;
+                return Err((col, "expected a number, name or '('".to_string()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/*
+ * Folds operations on constants, so e.g. `2*pi*f` costs the same as `6.28*f`.
+ * Each op's operands are the values left on the stack by the ops before it.
+ */
+fn fold_constants(ops: Vec<Op>) -> Vec<Op> {
+    let mut out: Vec<Op> = Vec::with_capacity(ops.len());
+
+    for op in ops {
+        let n = match op {
+            Op::Neg => 1,
+            Op::Bin(_) => 2,
+            Op::Call(f) => f.arity(),
+            _ => 0,
+        };
+
+        let args: Option<Vec<f32>> = (n > 0 && out.len() >= n)
+            .then(|| {
+                out[out.len() - n..]
+                    .iter()
+                    .map(|op| match op {
+                        Op::Const(c) => Some(*c),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .flatten();
+
+        match args {
+            Some(args) => {
+                out.truncate(out.len() - n);
+                let value = match op {
+                    Op::Neg => -args[0],
+                    Op::Bin(b) => b.apply(args[0], args[1]),
+                    Op::Call(f) => {
+                        let mut a = [0.0; 3];
+                        a[..n].copy_from_slice(&args);
+                        f.apply(a)
+                    }
+                    _ => unreachable!(),
+                };
+                out.push(Op::Const(value));
+            }
+            None => out.push(op),
+        }
+    }
+
+    out
+}
+
+/* The most values a program has on the stack at once */
+fn stack_depth(ops: &[Op]) -> usize {
+    let mut depth: usize = 0;
+    let mut max = 0;
+    for op in ops {
+        depth = match op {
+            Op::Const(_) | Op::Load(_) => depth + 1,
+            Op::Neg => depth,
+            Op::Bin(_) => depth - 1,
+            Op::Call(f) => depth + 1 - f.arity(),
+        };
+        max = max.max(depth);
+    }
+    max
+}
+
+#[allow(clippy::type_complexity)]
+fn compile(
+    src: &str,
+    bind: impl Fn(&str) -> Option<usize>,
+) -> Result<(Vec<Op>, Vec<(String, usize)>), (usize, String)> {
+    let tokens = tokenize(src)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        end: src.chars().count(),
+        bind,
+        ops: Vec::new(),
+        loads: Vec::new(),
+    };
+
+    parser.expr(0)?;
+    if parser.pos < tokens.len() {
+        return Err((parser.col(), "expected an operator".to_string()));
+    }
+
+    Ok((fold_constants(parser.ops), parser.loads))
+}
+
+impl Expr {
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        let param_obj = dict.obj("params")?;
+
+        // (output name, formula, path for errors)
+        let mut formulas = Vec::new();
+        match (param_obj.opt("expr"), param_obj.opt("exprs")) {
+            (Some(_), None) => {
+                formulas.push((
+                    "o".to_string(),
+                    param_obj.str("expr")?,
+                    param_obj.key_path("expr"),
+                ));
+            }
+            (None, Some(_)) => {
+                let exprs_obj = param_obj.obj("exprs")?;
+                for (name, _) in exprs_obj.dict().iter() {
+                    formulas.push((
+                        name.to_string(),
+                        exprs_obj.str(name)?,
+                        exprs_obj.key_path(name),
+                    ));
+                }
+                if formulas.is_empty() {
+                    return Err(param_obj.err("exprs", "expected at least one formula"));
+                }
+            }
+            _ => return Err(param_obj.err("expr", "expected one of expr or exprs")),
+        }
+
+        // Inputs are optional since names in the vars stanza bind themselves
+        let input_obj = match dict.opt("inputs") {
+            Some(_) => Some(dict.obj("inputs")?),
+            None => None,
+        };
+        let mut bound = Vec::new();
+        if let Some(input_obj) = &input_obj {
+            for (name, _) in input_obj.dict().iter() {
+                bound.push((name.to_string(), input_obj.index(name, VarType::Scalar)?));
+            }
+        }
+        let bind = |name: &str| {
+            bound
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, idx)| *idx)
+                .or_else(|| dict.named(VarType::Scalar, name))
+                .or_else(|| match name {
+                    "t" => dict.named(VarType::Scalar, NAME_ELAPSED),
+                    "x" => Some(SCALAR_X),
+                    "y" => Some(SCALAR_Y),
+                    _ => None,
+                })
+        };
+
+        let output_obj = dict.obj("outputs")?;
+
+        let mut programs = Vec::new();
+        for (name, src, path) in formulas {
+            let (ops, loads) = compile(src, bind).map_err(|(col, msg)| {
+                ConfigError::new(&path, format!("column {}: {msg}", col + 1))
+            })?;
+            let o_idx = output_obj.index(&name, VarType::Scalar)?;
+            programs.push(Program {
+                ops,
+                loads,
+                name,
+                o_idx,
+            });
+        }
+
+        let depth = programs
+            .iter()
+            .map(|p| stack_depth(&p.ops))
+            .max()
+            .unwrap_or(0);
+
+        Ok(Expr {
+            programs,
+            stack: Vec::with_capacity(depth),
+        })
+    }
+}
+
+impl RenderBlock for Expr {
+    fn execute(&mut self, state: &mut RenderState) {
+        let stack = &mut self.stack;
+
+        for program in &self.programs {
+            stack.clear();
+            for op in &program.ops {
+                match *op {
+                    Op::Const(c) => stack.push(c),
+                    Op::Load(idx) => stack.push(state.get_scalar(idx)),
+                    Op::Neg => {
+                        let a = stack.pop().unwrap();
+                        stack.push(-a);
+                    }
+                    Op::Bin(b) => {
+                        let rhs = stack.pop().unwrap();
+                        let lhs = stack.pop().unwrap();
+                        stack.push(b.apply(lhs, rhs));
+                    }
+                    Op::Call(f) => {
+                        let n = f.arity();
+                        let mut a = [0.0; 3];
+                        a[..n].copy_from_slice(&stack[stack.len() - n..]);
+                        stack.truncate(stack.len() - n);
+                        stack.push(f.apply(a));
+                    }
+                }
+            }
+
+            state.set_scalar(program.o_idx, stack[0]);
+        }
+    }
+
+    fn inputs(&self) -> Vec<Port> {
+        // Reading what an earlier formula in this block wrote is internal
+        let mut written = HashSet::new();
+        let mut ports: Vec<Port> = Vec::new();
+        for program in &self.programs {
+            for (name, idx) in &program.loads {
+                if !written.contains(idx) && !ports.iter().any(|p| p.index == *idx) {
+                    ports.push(Port::scalar(name.clone(), *idx));
+                }
+            }
+            written.insert(program.o_idx);
+        }
+        ports
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        self.programs
+            .iter()
+            .map(|p| Port::scalar(p.name.clone(), p.o_idx))
+            .collect()
+    }
+
+    fn purity(&self) -> Purity {
+        Purity::Pure
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* a, b and c are scalars 3-5, followed by "elapsed" and the output */
+    fn state(a: f32, b: f32, c: f32) -> RenderState {
+        let vars = json::object! {
+            float: [0, 0, 0, a, b, c, {name: "elapsed", value: 2.5}, 0],
+            position: [], color: [], rcolor: [], data: [],
+        };
+        RenderState::from_obj(&vars, "").unwrap()
+    }
+
+    fn build(src: &str, state: &RenderState) -> Result<Expr, ConfigError> {
+        let block = json::object! {
+            params: {expr: src},
+            inputs: {a: 3, b: 4, c: 5},
+            outputs: {o: 7},
+        };
+        Expr::from_obj(
+            &ConfigObj::new(&block, "")
+                .unwrap()
+                .with_names(state.names()),
+        )
+    }
+
+    fn eval(src: &str, a: f32, b: f32, c: f32) -> f32 {
+        let mut state = state(a, b, c);
+        build(src, &state).unwrap().execute(&mut state);
+        state.get_scalar(7)
+    }
+
+    fn error(src: &str) -> String {
+        build(src, &state(0.0, 0.0, 0.0)).err().unwrap().to_string()
+    }
+
+    /* The ops for a formula with a bound to scalar 3 */
+    fn ops(src: &str) -> Vec<Op> {
+        compile(src, |name| (name == "a").then_some(3)).unwrap().0
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("-a^2", 3.0, 0.0, 0.0), -9.0);
+        assert_eq!(eval("2^-1*3", 0.0, 0.0, 0.0), 1.5);
+        assert_eq!(eval("a+b*c", 1.0, 2.0, 3.0), 7.0);
+        assert_eq!(eval("a*b<c", 1.0, 2.0, 3.0), 1.0);
+    }
+
+    #[test]
+    fn associativity() {
+        // (5 < 4) < 3, where 5 < (4 < 3) would be 0
+        assert_eq!(eval("a<b<c", 5.0, 4.0, 3.0), 1.0);
+        assert_eq!(eval("a-b-c", 10.0, 4.0, 3.0), 3.0);
+        assert_eq!(eval("a/b/c", 24.0, 4.0, 3.0), 2.0);
+        assert_eq!(eval("2^3^2", 0.0, 0.0, 0.0), 512.0);
+    }
+
+    #[test]
+    fn bindings() {
+        assert_eq!(eval("elapsed*2", 0.0, 0.0, 0.0), 5.0);
+
+        let mut state = state(0.0, 0.0, 0.0);
+        state.set_scalar(SCALAR_X, 2.0);
+        state.set_scalar(SCALAR_Y, 3.0);
+        build("x*y + t", &state).unwrap().execute(&mut state);
+        assert_eq!(state.get_scalar(7), 8.5);
+
+        // Without a float named "elapsed", t has to be bound
+        let vars = json::object! {
+            float: [0, 0, 0, 0, 0, 0, 0, 0],
+            position: [], color: [], rcolor: [], data: [],
+        };
+        let bare = RenderState::from_obj(&vars, "").unwrap();
+        let err = build("t", &bare).err().unwrap().to_string();
+        assert_eq!(
+            err,
+            "params.expr: column 1: 't' is not an input or named float"
+        );
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(
+            error("sin(a, b)"),
+            "params.expr: column 6: expected ')', sin takes 1 argument(s)"
+        );
+        assert_eq!(error("atan2(a)"), "params.expr: column 8: expected ','");
+        assert_eq!(error("clamp(a, b)"), "params.expr: column 11: expected ','");
+    }
+
+    #[test]
+    fn folding() {
+        use std::f32::consts::TAU;
+
+        assert_eq!(
+            ops("2*pi*a"),
+            vec![Op::Const(TAU), Op::Load(3), Op::Bin(BinOp::Mul)]
+        );
+        assert_eq!(ops("-(1+2)"), vec![Op::Const(-3.0)]);
+        assert_eq!(
+            ops("a+sqrt(4)"),
+            vec![Op::Load(3), Op::Const(2.0), Op::Bin(BinOp::Add)]
+        );
+        // Left to right, a*2 isn't constant so nothing folds
+        assert_eq!(ops("a*2*3").len(), 5);
+    }
+
+    #[test]
+    fn error_columns() {
+        assert_eq!(
+            error("a + * b"),
+            "params.expr: column 5: expected a number, name or '('"
+        );
+        assert_eq!(
+            error("a + nope"),
+            "params.expr: column 5: 'nope' is not an input or named float"
+        );
+        assert_eq!(
+            error("foo(a)"),
+            "params.expr: column 1: unknown function 'foo'"
+        );
+        assert_eq!(error("(a + 1"), "params.expr: column 7: expected ')'");
+        assert_eq!(error("a b"), "params.expr: column 3: expected an operator");
+    }
+}
@@ -1,10 +1,11 @@
-use json::JsonValue;
 use num_traits::{clamp, Pow};
 //use rand::Rng;
 
-use crate::render_block::{RenderBlock, RenderState};
-use crate::var_types::Color;
+use crate::config::{ConfigError, ConfigObj};
+use crate::render_block::{Port, Purity, RenderBlock, RenderState};
+use crate::var_types::{Color, VarType};
 
+#[derive(Clone)]
 pub struct Gamma {
     // Params
     gamma: f32,
@@ -16,82 +17,35 @@ pub struct Gamma {
     // Inputs
     i_idx: usize,
 
-    x_idx: usize,
-    y_idx: usize,
-
     // Outputs
     o_idx: usize,
 }
 
 impl Gamma {
-    pub fn from_obj(dict: &json::object::Object) -> Self {
-        let input_obj = match dict.get("params").expect("Missing params definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for Gamma params is not an object"),
-        };
-        let gamma = input_obj
-            .get("gamma")
-            .expect("Missing gamma input")
-            .as_f32()
-            .expect("Could not parse gamma input");
-        let rc = input_obj
-            .get("rc")
-            .expect("Missing R coefficient input")
-            .as_f32()
-            .expect("Could not parse R coefficient input");
-        let gc = input_obj
-            .get("gc")
-            .expect("Missing G coefficient input")
-            .as_f32()
-            .expect("Could not parse G coefficient input");
-        let bc = input_obj
-            .get("bc")
-            .expect("Missing B coefficient input")
-            .as_f32()
-            .expect("Could not parse B coefficient input");
-
-        let input_obj = match dict.get("inputs").expect("Missing input definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for Gamma inputs is not an object"),
-        };
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        let param_obj = dict.obj("params")?;
 
-        let i_idx = input_obj
-            .get("i")
-            .expect("Missing i input")
-            .as_usize()
-            .expect("Could not parse i input");
-        let x_idx = input_obj
-            .get("x")
-            .expect("Missing x input")
-            .as_usize()
-            .expect("Could not parse x input");
-        let y_idx = input_obj
-            .get("y")
-            .expect("Missing y input")
-            .as_usize()
-            .expect("Could not parse y input");
-
-        let output_obj = match dict.get("outputs").expect("Missing output definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for Gamma outputs is not an object"),
-        };
+        let gamma = param_obj.f32("gamma")?;
+        let rc = param_obj.f32("rc")?;
+        let gc = param_obj.f32("gc")?;
+        let bc = param_obj.f32("bc")?;
+
+        let input_obj = dict.obj("inputs")?;
+
+        let i_idx = input_obj.index("i", VarType::RColor)?;
+
+        let output_obj = dict.obj("outputs")?;
 
-        let o_idx = output_obj
-            .get("o")
-            .expect("Missing o output")
-            .as_usize()
-            .expect("Could not parse o output");
+        let o_idx = output_obj.index("o", VarType::Color)?;
 
-        Gamma {
+        Ok(Gamma {
             gamma,
             rc,
             gc,
             bc,
             i_idx,
-            x_idx,
-            y_idx,
             o_idx,
-        }
+        })
     }
 }
 
@@ -119,4 +73,16 @@ impl RenderBlock for Gamma {
 
         state.set_color(self.o_idx, c);
     }
+
+    fn inputs(&self) -> Vec<Port> {
+        vec![Port::new("i", VarType::RColor, self.i_idx)]
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        vec![Port::new("o", VarType::Color, self.o_idx)]
+    }
+
+    fn purity(&self) -> Purity {
+        Purity::Pure
+    }
 }
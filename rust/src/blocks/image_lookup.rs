@@ -1,11 +1,16 @@
-use crate::render_block::{RenderBlock, RenderState};
-use crate::var_types::Color;
+use crate::config::{ConfigError, ConfigObj};
+use crate::constants;
+use crate::render_block::{Port, Purity, RenderBlock, RenderState};
+use crate::var_types::{Color, Position, VarType};
 
-use json::JsonValue;
-use num_traits::ToPrimitive;
 use num_enum::FromPrimitive;
 
+#[derive(Clone)]
 pub struct ImageLookup {
+    // Params
+    filter: Filter,
+    channels: usize,
+
     // Inputs
     width_idx: usize,
     height_idx: usize,
@@ -16,122 +21,227 @@ pub struct ImageLookup {
     mode_idx: usize,
     data_idx: usize,
 
+    offset_idx: Option<usize>, // position
+    scale_idx: Option<usize>,
+    rotation_idx: Option<usize>,
+
     // Outputs
     o_idx: usize, // color
+    alpha_idx: Option<usize>,
 }
 
-#[derive(Debug, Clone, PartialEq, FromPrimitive)]
+/* What lies outside the image */
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
 #[repr(u8)]
 enum LookupMode {
+    /* Transparent black */
     #[num_enum(default)]
     Single,
+    /* The image repeated */
     Tile,
+    /* The image repeated, every other copy flipped so the edges meet */
+    Mirror,
+    /* The nearest edge pixel */
+    Clamp,
 }
 
-impl ImageLookup {
-    pub fn from_obj(dict: &json::object::Object) -> Self {
-        let input_obj = match dict.get("inputs").expect("Missing input definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ImageLookup inputs is not an object"),
+impl LookupMode {
+    /* Where coordinate i of an image n pixels across reads from, if anywhere */
+    fn wrap(self, i: isize, n: isize) -> Option<usize> {
+        let i = match self {
+            LookupMode::Single => i,
+            LookupMode::Tile => i.rem_euclid(n),
+            LookupMode::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n {
+                    i
+                } else {
+                    2 * n - 1 - i
+                }
+            }
+            LookupMode::Clamp => i.clamp(0, n - 1),
         };
+        (0..n).contains(&i).then_some(i as usize)
+    }
+}
 
-        let width_idx = input_obj
-            .get("width")
-            .expect("Missing width input")
-            .as_usize()
-            .expect("Could not parse width input");
-        let height_idx = input_obj
-            .get("height")
-            .expect("Missing height input")
-            .as_usize()
-            .expect("Could not parse height input");
-
-        let x_idx = input_obj
-            .get("x")
-            .expect("Missing x input")
-            .as_usize()
-            .expect("Could not parse x input");
-        let y_idx = input_obj
-            .get("y")
-            .expect("Missing y input")
-            .as_usize()
-            .expect("Could not parse y input");
-
-        let mode_idx = input_obj
-            .get("mode")
-            .expect("Missing mode input")
-            .as_usize()
-            .expect("Could not parse mode input");
-        let data_idx = input_obj
-            .get("data")
-            .expect("Missing data input")
-            .as_usize()
-            .expect("Could not parse data input");
-
-        let output_obj = match dict.get("outputs").expect("Missing output definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ImageLookup outputs is not an object"),
-        };
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Filter {
+    Nearest,
+    Bilinear,
+}
 
-        let o_idx = output_obj
-            .get("o")
-            .expect("Missing o output")
-            .as_usize()
-            .expect("Could not parse o output");
+impl ImageLookup {
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        // Params are optional, defaulting to how this block always worked
+        let mut filter = Filter::Nearest;
+        let mut channels = 3;
+        if dict.opt("params").is_some() {
+            let param_obj = dict.obj("params")?;
+
+            if param_obj.opt("filter").is_some() {
+                filter = match param_obj.str("filter")? {
+                    "nearest" => Filter::Nearest,
+                    "bilinear" => Filter::Bilinear,
+                    _ => return Err(param_obj.err("filter", "expected 'nearest' or 'bilinear'")),
+                };
+            }
+            if param_obj.opt("format").is_some() {
+                channels = match param_obj.str("format")? {
+                    "rgb" => 3,
+                    "rgba" => 4,
+                    _ => return Err(param_obj.err("format", "expected 'rgb' or 'rgba'")),
+                };
+            }
+        }
+
+        let input_obj = dict.obj("inputs")?;
+
+        let width_idx = input_obj.index("width", VarType::Scalar)?;
+        let height_idx = input_obj.index("height", VarType::Scalar)?;
+
+        let x_idx = input_obj.index("x", VarType::Scalar)?;
+        let y_idx = input_obj.index("y", VarType::Scalar)?;
 
-        ImageLookup {
+        let mode_idx = input_obj.index("mode", VarType::Scalar)?;
+        let data_idx = input_obj.index("data", VarType::Data)?;
+
+        let offset_idx = input_obj.opt_index("offset", VarType::Position)?;
+        let scale_idx = input_obj.opt_index("scale", VarType::Scalar)?;
+        let rotation_idx = input_obj.opt_index("rotation", VarType::Scalar)?;
+
+        let output_obj = dict.obj("outputs")?;
+
+        let o_idx = output_obj.index("o", VarType::Color)?;
+        let alpha_idx = output_obj.opt_index("alpha", VarType::Scalar)?;
+
+        Ok(ImageLookup {
+            filter,
+            channels,
             width_idx,
             height_idx,
             x_idx,
             y_idx,
             mode_idx,
             data_idx,
+            offset_idx,
+            scale_idx,
+            rotation_idx,
             o_idx,
-        }
+            alpha_idx,
+        })
+    }
+
+    /* Premultiplied RGBA of pixel (i, j), transparent if it's missing */
+    fn texel(&self, data: &[u8], i: usize, j: usize, width: usize) -> [f32; 4] {
+        let idx = self.channels * (i + j * width);
+        let Some(p) = data.get(idx..idx + self.channels) else {
+            return [0.0; 4];
+        };
+
+        let a = if self.channels == 4 {
+            p[3] as f32 / 255.0
+        } else {
+            1.0
+        };
+        [p[0] as f32 * a, p[1] as f32 * a, p[2] as f32 * a, a]
     }
 }
 
 impl RenderBlock for ImageLookup {
     fn execute(&mut self, state: &mut RenderState) {
-        let width = state.get_scalar(self.width_idx).round().clamp(1.0, 1024.0);
-        let height = state.get_scalar(self.height_idx).round().clamp(1.0, 1024.0);
-
-        let x = state.get_scalar(self.x_idx) * 1.0;
-        let y = state.get_scalar(self.y_idx) * 2.2;
+        let max = constants::MAX_IMAGE_SIDE as f32;
+        let width = state.get_scalar(self.width_idx).round().clamp(1.0, max) as isize;
+        let height = state.get_scalar(self.height_idx).round().clamp(1.0, max) as isize;
+
+        let offset = self
+            .offset_idx
+            .map_or(Position::default(), |idx| *state.get_position(idx));
+        let scale = self.scale_idx.map_or(1.0, |idx| state.get_scalar(idx));
+        let rotation = self.rotation_idx.map_or(0.0, |idx| state.get_scalar(idx));
+
+        // One image pixel per LED along a string, squared up across the strings
+        let x = state.get_scalar(self.x_idx) - offset.x;
+        let y = (state.get_scalar(self.y_idx) - offset.y) / constants::X_SCALE;
+
+        // Rotate and scale about the image's center, radians clockwise on the grid
+        let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+        let (sin, cos) = (-rotation).sin_cos();
+        let scale = if scale.abs() > f32::EPSILON {
+            scale
+        } else {
+            f32::EPSILON
+        };
+        let u = cx + (cos * (x - cx) - sin * (y - cy)) / scale;
+        let v = cy + (sin * (x - cx) + cos * (y - cy)) / scale;
 
+        // Anything that isn't a known mode reads as Single, including NaN
+        let mode = LookupMode::from(state.get_scalar(self.mode_idx).round() as u8);
         let data = state.get_data(self.data_idx);
+        let fetch = |i: isize, j: isize| match (mode.wrap(i, width), mode.wrap(j, height)) {
+            (Some(i), Some(j)) => self.texel(data, i, j, width as usize),
+            _ => [0.0; 4],
+        };
 
-        let mut i = -1;
-        let mut j = -1;
-
-        match LookupMode::from(state.get_scalar(self.mode_idx).to_u8().unwrap()) {
-            LookupMode::Single => {
-                if x >= 0.0 && x < width {
-                    i = x.round().to_isize().unwrap();
-                }
-                if y >= 0.0 && y < height {
-                    j = y.round().to_isize().unwrap();
+        let p = match self.filter {
+            Filter::Nearest => fetch(u.round() as isize, v.round() as isize),
+            Filter::Bilinear => {
+                let (i, j) = (u.floor(), v.floor());
+                let (fu, fv) = (u - i, v - j);
+                let (i, j) = (i as isize, j as isize);
+
+                let mut p = [0.0; 4];
+                for (di, dj, w) in [
+                    (0, 0, (1.0 - fu) * (1.0 - fv)),
+                    (1, 0, fu * (1.0 - fv)),
+                    (0, 1, (1.0 - fu) * fv),
+                    (1, 1, fu * fv),
+                ] {
+                    let t = fetch(i + di, j + dj);
+                    (0..4).for_each(|c| p[c] += w * t[c]);
                 }
+                p
             }
-            LookupMode::Tile => {
-                i = x.rem_euclid(width).round().to_isize().unwrap();
-                j = y.rem_euclid(height).round().to_isize().unwrap();
-            }
+        };
+
+        // The color is premultiplied, i.e. already composited over black
+        let [r, g, b] = [p[0], p[1], p[2]].map(|c| c.round().clamp(0.0, 255.0) as u8);
+        state.set_color(self.o_idx, Color { r, g, b });
+        if let Some(idx) = self.alpha_idx {
+            state.set_scalar(idx, p[3]);
         }
+    }
 
-        let mut r = 0u8;
-        let mut g = 0u8;
-        let mut b = 0u8;
+    fn inputs(&self) -> Vec<Port> {
+        let mut ports = vec![
+            Port::scalar("width", self.width_idx),
+            Port::scalar("height", self.height_idx),
+            Port::scalar("x", self.x_idx),
+            Port::scalar("y", self.y_idx),
+            Port::scalar("mode", self.mode_idx),
+            Port::new("data", VarType::Data, self.data_idx),
+        ];
+        if let Some(idx) = self.offset_idx {
+            ports.push(Port::new("offset", VarType::Position, idx));
+        }
+        if let Some(idx) = self.scale_idx {
+            ports.push(Port::scalar("scale", idx));
+        }
+        if let Some(idx) = self.rotation_idx {
+            ports.push(Port::scalar("rotation", idx));
+        }
+        ports
+    }
 
-        if i >= 0 && j >= 0 {
-            let idx = 3*(i + j*(width as isize)) as usize;
-            if idx + 2 < data.len() {
-                r = data[idx + 0];
-                g = data[idx + 1];
-                b = data[idx + 2];
-            }
+    fn outputs(&self) -> Vec<Port> {
+        let mut ports = vec![Port::new("o", VarType::Color, self.o_idx)];
+        if let Some(idx) = self.alpha_idx {
+            ports.push(Port::scalar("alpha", idx));
         }
+        ports
+    }
 
-        state.set_color(self.o_idx, Color {r, g, b});
+    fn purity(&self) -> Purity {
+        Purity::Pure
     }
 }
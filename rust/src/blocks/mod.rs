@@ -1,48 +1,61 @@
 pub mod color_interp;
 pub mod dither;
+pub mod expr;
 pub mod gamma;
 pub mod image_lookup;
+pub mod noise;
+pub mod palette;
 pub mod scalar_add;
 pub mod scalar_hsv2rgb;
 pub mod scalar_macc;
 pub mod scalar_ramp;
 pub mod scalar_triangle;
+pub mod wander;
 
 use json::JsonValue;
 
+use crate::config::{ConfigError, ConfigObj};
 use crate::render_block::RenderBlock;
+use crate::var_types::VarNames;
 use color_interp::ColorInterp;
 use dither::Dither;
+use expr::Expr;
 use gamma::Gamma;
 use image_lookup::ImageLookup;
+use noise::Noise;
+use palette::Palette;
 use scalar_add::ScalarAdd;
 use scalar_hsv2rgb::ScalarHsv2Rgb;
 use scalar_macc::ScalarMacc;
 use scalar_ramp::ScalarRamp;
 use scalar_triangle::ScalarTriangle;
+use wander::WanderBlock;
 
-pub fn block_factory(v: &JsonValue) -> Box<dyn RenderBlock> {
-    let dict = match v {
-        JsonValue::Object(ref x) => x,
-        _ => panic!("Position is not an object"),
-    };
+/* `names` resolves variable references given by name rather than index */
+pub fn block_factory(
+    v: &JsonValue,
+    path: &str,
+    names: &VarNames,
+) -> Result<Box<dyn RenderBlock>, ConfigError> {
+    let dict = ConfigObj::new(v, path)?.with_names(names);
 
-    let name = dict
-        .get("type")
-        .expect("Render block missing name")
-        .as_str()
-        .expect("Render block name is not a string");
+    let name = dict.str("type")?;
 
-    match name {
-        "color_interp" => Box::new(ColorInterp::from_obj(dict)),
-        "dither" => Box::new(Dither::from_obj(dict)),
-        "gamma" => Box::new(Gamma::from_obj(dict)),
-        "image_lookup" => Box::new(ImageLookup::from_obj(dict)),
-        "scalar_add" => Box::new(ScalarAdd::from_obj(dict)),
-        "scalar_hsv2rgb" => Box::new(ScalarHsv2Rgb::from_obj(dict)),
-        "scalar_macc" => Box::new(ScalarMacc::from_obj(dict)),
-        "scalar_ramp" => Box::new(ScalarRamp::from_obj(dict)),
-        "scalar_triangle" => Box::new(ScalarTriangle::from_obj(dict)),
-        _ => panic!("Unknown RenderBlock {}", name),
-    }
+    Ok(match name {
+        "color_interp" => Box::new(ColorInterp::from_obj(&dict)?),
+        "dither" => Box::new(Dither::from_obj(&dict)?),
+        "expr" => Box::new(Expr::from_obj(&dict)?),
+        "gamma" => Box::new(Gamma::from_obj(&dict)?),
+        "image_lookup" => Box::new(ImageLookup::from_obj(&dict)?),
+        "noise2d" => Box::new(Noise::from_obj(&dict, 2)?),
+        "noise3d" => Box::new(Noise::from_obj(&dict, 3)?),
+        "palette" => Box::new(Palette::from_obj(&dict)?),
+        "scalar_add" => Box::new(ScalarAdd::from_obj(&dict)?),
+        "scalar_hsv2rgb" => Box::new(ScalarHsv2Rgb::from_obj(&dict)?),
+        "scalar_macc" => Box::new(ScalarMacc::from_obj(&dict)?),
+        "scalar_ramp" => Box::new(ScalarRamp::from_obj(&dict)?),
+        "scalar_triangle" => Box::new(ScalarTriangle::from_obj(&dict)?),
+        "wander" => Box::new(WanderBlock::from_obj(&dict)?),
+        _ => return Err(dict.err("type", format!("unknown render block '{name}'"))),
+    })
 }
@@ -0,0 +1,426 @@
+use crate::config::{ConfigError, ConfigObj};
+use crate::render_block::{Port, Purity, RenderBlock, RenderState};
+use crate::var_types::VarType;
+
+/*
+ * Gradient noise for organic textures. noise2d samples (x, y) and noise3d
+ * samples (x, y, t), so feeding it the elapsed time makes a 2D field that
+ * evolves smoothly. Octaves are summed as fractal Brownian motion, each one
+ * `lacunarity` times the frequency and `gain` times the amplitude of the one
+ * before. The output is roughly 0 to 1.
+ *
+ * The permutation table is shuffled with SplitMix64 rather than the rand
+ * crate, so a given seed gives the same field on every build.
+ */
+#[derive(Clone)]
+pub struct Noise {
+    // Params
+    kind: NoiseKind,
+    frequency: f32,
+    octaves: usize,
+    lacunarity: f32,
+    gain: f32,
+    perm: Box<[u8; 512]>,
+
+    // Inputs
+    x_idx: usize,
+    y_idx: usize,
+    t_idx: Option<usize>,
+
+    // Outputs
+    o_idx: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NoiseKind {
+    Perlin,
+    Simplex,
+}
+
+/* Keeps octaves from lining up at the origin, where every octave is zero */
+const OCTAVE_SHIFT: f32 = 19.19;
+
+/* Scales each kind of noise to about -1 to 1 */
+const PERLIN_SCALE: f32 = 1.1;
+const SIMPLEX2_SCALE: f32 = 70.0;
+const SIMPLEX3_SCALE: f32 = 32.0;
+
+const SKEW2: f32 = 0.366_025_42; // (sqrt(3) - 1) / 2
+const UNSKEW2: f32 = 0.211_324_87; // (3 - sqrt(3)) / 6
+const SKEW3: f32 = 1.0 / 3.0;
+const UNSKEW3: f32 = 1.0 / 6.0;
+
+/* The edge midpoints of a cube, as in Ken Perlin's improved noise */
+const GRAD3: [[f32; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/* A Fisher-Yates shuffle of 0-255, doubled so lookups never need wrapping */
+fn permutation(seed: u64) -> Box<[u8; 512]> {
+    let mut p: [u8; 256] = std::array::from_fn(|i| i as u8);
+    let mut state = seed;
+    for i in (1..256).rev() {
+        let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+        p.swap(i, j);
+    }
+
+    Box::new(std::array::from_fn(|i| p[i & 255]))
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + t * (b - a)
+}
+
+impl Noise {
+    pub fn from_obj(dict: &ConfigObj, dims: usize) -> Result<Self, ConfigError> {
+        let mut kind = NoiseKind::Perlin;
+        let mut frequency = 1.0;
+        let mut octaves = 1;
+        let mut lacunarity = 2.0;
+        let mut gain = 0.5;
+        let mut seed = 0;
+
+        if dict.opt("params").is_some() {
+            let param_obj = dict.obj("params")?;
+            let opt_f32 = |key, value: &mut f32| {
+                if param_obj.opt(key).is_some() {
+                    *value = param_obj.f32(key)?;
+                }
+                Ok::<_, ConfigError>(())
+            };
+
+            if param_obj.opt("kind").is_some() {
+                kind = match param_obj.str("kind")? {
+                    "perlin" => NoiseKind::Perlin,
+                    "simplex" => NoiseKind::Simplex,
+                    _ => return Err(param_obj.err("kind", "expected 'perlin' or 'simplex'")),
+                };
+            }
+            opt_f32("frequency", &mut frequency)?;
+            opt_f32("lacunarity", &mut lacunarity)?;
+            opt_f32("gain", &mut gain)?;
+            if param_obj.opt("octaves").is_some() {
+                octaves = param_obj.usize("octaves")?;
+                if !(1..=12).contains(&octaves) {
+                    return Err(param_obj.err("octaves", "expected 1 to 12 octaves"));
+                }
+            }
+            if param_obj.opt("seed").is_some() {
+                seed = param_obj.usize("seed")? as u64;
+            }
+        }
+
+        let input_obj = dict.obj("inputs")?;
+
+        let x_idx = input_obj.index("x", VarType::Scalar)?;
+        let y_idx = input_obj.index("y", VarType::Scalar)?;
+        let t_idx = match dims {
+            3 => Some(input_obj.index("t", VarType::Scalar)?),
+            _ => None,
+        };
+
+        let output_obj = dict.obj("outputs")?;
+
+        let o_idx = output_obj.index("o", VarType::Scalar)?;
+
+        Ok(Noise {
+            kind,
+            frequency,
+            octaves,
+            lacunarity,
+            gain,
+            perm: permutation(seed),
+            x_idx,
+            y_idx,
+            t_idx,
+            o_idx,
+        })
+    }
+
+    fn hash2(&self, i: i32, j: i32) -> usize {
+        let p = &self.perm;
+        p[p[(i & 255) as usize] as usize + (j & 255) as usize] as usize
+    }
+
+    fn hash3(&self, i: i32, j: i32, k: i32) -> usize {
+        let p = &self.perm;
+        p[self.hash2(i, j) + (k & 255) as usize] as usize
+    }
+
+    fn grad2(&self, i: i32, j: i32, x: f32, y: f32) -> f32 {
+        let g = GRAD3[self.hash2(i, j) % 12];
+        g[0] * x + g[1] * y
+    }
+
+    fn grad3(&self, i: i32, j: i32, k: i32, x: f32, y: f32, z: f32) -> f32 {
+        let g = GRAD3[self.hash3(i, j, k) % 12];
+        g[0] * x + g[1] * y + g[2] * z
+    }
+
+    fn perlin2(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (i, j) = (x0 as i32, y0 as i32);
+        let (u, v) = (fade(fx), fade(fy));
+
+        let a = lerp(
+            self.grad2(i, j, fx, fy),
+            self.grad2(i + 1, j, fx - 1.0, fy),
+            u,
+        );
+        let b = lerp(
+            self.grad2(i, j + 1, fx, fy - 1.0),
+            self.grad2(i + 1, j + 1, fx - 1.0, fy - 1.0),
+            u,
+        );
+        lerp(a, b, v) * PERLIN_SCALE
+    }
+
+    fn perlin3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (i, j, k) = (x0 as i32, y0 as i32, z0 as i32);
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+
+        let corner = |di: i32, dj: i32, dk: i32| {
+            self.grad3(
+                i + di,
+                j + dj,
+                k + dk,
+                fx - di as f32,
+                fy - dj as f32,
+                fz - dk as f32,
+            )
+        };
+
+        let a = lerp(corner(0, 0, 0), corner(1, 0, 0), u);
+        let b = lerp(corner(0, 1, 0), corner(1, 1, 0), u);
+        let c = lerp(corner(0, 0, 1), corner(1, 0, 1), u);
+        let d = lerp(corner(0, 1, 1), corner(1, 1, 1), u);
+        lerp(lerp(a, b, v), lerp(c, d, v), w) * PERLIN_SCALE
+    }
+
+    /* Stefan Gustavson's formulation of 2D simplex noise */
+    fn simplex2(&self, x: f32, y: f32) -> f32 {
+        let s = (x + y) * SKEW2;
+        let (i, j) = ((x + s).floor(), (y + s).floor());
+        let t = (i + j) * UNSKEW2;
+        let (x0, y0) = (x - (i - t), y - (j - t));
+        let (i, j) = (i as i32, j as i32);
+
+        // Which of the two triangles in the skewed square we're in
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+
+        let corners = [
+            (0, 0, x0, y0),
+            (i1, j1, x0 - i1 as f32 + UNSKEW2, y0 - j1 as f32 + UNSKEW2),
+            (1, 1, x0 - 1.0 + 2.0 * UNSKEW2, y0 - 1.0 + 2.0 * UNSKEW2),
+        ];
+
+        let n: f32 = corners
+            .iter()
+            .map(|&(di, dj, cx, cy)| {
+                let t = 0.5 - cx * cx - cy * cy;
+                if t < 0.0 {
+                    0.0
+                } else {
+                    t.powi(4) * self.grad2(i + di, j + dj, cx, cy)
+                }
+            })
+            .sum();
+        n * SIMPLEX2_SCALE
+    }
+
+    /* Stefan Gustavson's formulation of 3D simplex noise */
+    fn simplex3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let s = (x + y + z) * SKEW3;
+        let (i, j, k) = ((x + s).floor(), (y + s).floor(), (z + s).floor());
+        let t = (i + j + k) * UNSKEW3;
+        let (x0, y0, z0) = (x - (i - t), y - (j - t), z - (k - t));
+        let (i, j, k) = (i as i32, j as i32, k as i32);
+
+        // Which of the six tetrahedra in the skewed cube we're in
+        let ((i1, j1, k1), (i2, j2, k2)) = if x0 >= y0 {
+            if y0 >= z0 {
+                ((1, 0, 0), (1, 1, 0))
+            } else if x0 >= z0 {
+                ((1, 0, 0), (1, 0, 1))
+            } else {
+                ((0, 0, 1), (1, 0, 1))
+            }
+        } else if y0 < z0 {
+            ((0, 0, 1), (0, 1, 1))
+        } else if x0 < z0 {
+            ((0, 1, 0), (0, 1, 1))
+        } else {
+            ((0, 1, 0), (1, 1, 0))
+        };
+
+        let offset = |di: i32, dj: i32, dk: i32, n: f32| {
+            (
+                di,
+                dj,
+                dk,
+                x0 - di as f32 + n * UNSKEW3,
+                y0 - dj as f32 + n * UNSKEW3,
+                z0 - dk as f32 + n * UNSKEW3,
+            )
+        };
+        let corners = [
+            offset(0, 0, 0, 0.0),
+            offset(i1, j1, k1, 1.0),
+            offset(i2, j2, k2, 2.0),
+            offset(1, 1, 1, 3.0),
+        ];
+
+        let n: f32 = corners
+            .iter()
+            .map(|&(di, dj, dk, cx, cy, cz)| {
+                let t = 0.6 - cx * cx - cy * cy - cz * cz;
+                if t < 0.0 {
+                    0.0
+                } else {
+                    t.powi(4) * self.grad3(i + di, j + dj, k + dk, cx, cy, cz)
+                }
+            })
+            .sum();
+        n * SIMPLEX3_SCALE
+    }
+
+    fn sample(&self, x: f32, y: f32, t: Option<f32>) -> f32 {
+        match (self.kind, t) {
+            (NoiseKind::Perlin, None) => self.perlin2(x, y),
+            (NoiseKind::Perlin, Some(t)) => self.perlin3(x, y, t),
+            (NoiseKind::Simplex, None) => self.simplex2(x, y),
+            (NoiseKind::Simplex, Some(t)) => self.simplex3(x, y, t),
+        }
+    }
+}
+
+impl RenderBlock for Noise {
+    fn execute(&mut self, state: &mut RenderState) {
+        let x = state.get_scalar(self.x_idx);
+        let y = state.get_scalar(self.y_idx);
+        let t = self.t_idx.map(|idx| state.get_scalar(idx));
+
+        let mut freq = self.frequency;
+        let mut amp = 1.0;
+        let mut sum = 0.0;
+        let mut norm = 0.0;
+        for octave in 0..self.octaves {
+            let shift = octave as f32 * OCTAVE_SHIFT;
+            sum += amp
+                * self.sample(
+                    x * freq + shift,
+                    y * freq + shift,
+                    t.map(|t| t * freq + shift),
+                );
+            norm += amp;
+            freq *= self.lacunarity;
+            amp *= self.gain;
+        }
+
+        let out = if norm > 0.0 { sum / norm } else { 0.0 };
+        state.set_scalar(self.o_idx, (out * 0.5 + 0.5).clamp(0.0, 1.0));
+    }
+
+    fn inputs(&self) -> Vec<Port> {
+        let mut ports = vec![Port::scalar("x", self.x_idx), Port::scalar("y", self.y_idx)];
+        if let Some(idx) = self.t_idx {
+            ports.push(Port::scalar("t", idx));
+        }
+        ports
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        vec![Port::scalar("o", self.o_idx)]
+    }
+
+    fn purity(&self) -> Purity {
+        Purity::Pure
+    }
+}
+
+/*
+ * Configs are tuned by eye against a seed, so the field for a given seed must
+ * not drift. These pin a few samples of 3 octaves with seed 42.
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Runs a block with x, y and t in scalars 0-2 over each (x, y, t) */
+    fn samples(kind: &str, dims: usize, points: &[(f32, f32, f32)]) -> Vec<f32> {
+        let block = json::object! {
+            params: {kind: kind, octaves: 3, seed: 42},
+            inputs: {x: 0, y: 1, t: 2},
+            outputs: {o: 3},
+        };
+        let mut noise = Noise::from_obj(&ConfigObj::new(&block, "").unwrap(), dims).unwrap();
+
+        let vars = json::object! {
+            float: [0, 0, 0, 0], position: [], color: [], rcolor: [], data: [],
+        };
+        let mut state = RenderState::from_obj(&vars, "").unwrap();
+
+        points
+            .iter()
+            .map(|&(x, y, t)| {
+                state.set_scalar(0, x);
+                state.set_scalar(1, y);
+                state.set_scalar(2, t);
+                noise.execute(&mut state);
+                state.get_scalar(3)
+            })
+            .collect()
+    }
+
+    const POINTS: [(f32, f32, f32); 4] = [
+        (0.0, 0.0, 0.0),
+        (0.3, 1.7, 0.5),
+        (12.25, 3.5, 2.0),
+        (-4.8, 9.1, 7.75),
+    ];
+
+    fn check(kind: &str, dims: usize, expected: [f32; 4]) {
+        let got = samples(kind, dims, &POINTS);
+        for (g, e) in got.iter().zip(expected) {
+            assert!((g - e).abs() < 1e-5, "{kind} {dims}d: got {got:?}");
+        }
+    }
+
+    #[test]
+    fn perlin_is_unchanged() {
+        check("perlin", 2, [0.539728, 0.67883873, 0.63874143, 0.5829453]);
+        check("perlin", 3, [0.5132746, 0.31600514, 0.37235457, 0.54554516]);
+    }
+
+    #[test]
+    fn simplex_is_unchanged() {
+        check("simplex", 2, [0.54165727, 0.6175719, 0.5102369, 0.3755613]);
+        check("simplex", 3, [0.5747852, 0.4956603, 0.5234577, 0.24238482]);
+    }
+}
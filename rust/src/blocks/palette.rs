@@ -0,0 +1,155 @@
+use crate::config::{ConfigError, ConfigObj};
+use crate::gradient::{self, Gradient, Mode, Space, Stop, BUILTIN_NAMES};
+use crate::render_block::{Port, Purity, RenderBlock, RenderState};
+use crate::var_types::{Color, FromJson, VarType};
+
+/*
+ * Looks up a color along a gradient, e.g.
+ *   {"type": "palette", "params": {"map": "sky", "mode": "mirror"},
+ *    "inputs": {"i": "phase"}, "outputs": {"o": "out"}}
+ *
+ * The stops are one of the built in "map"s, given inline as "stops" in the
+ * same form as the gradient pool, or read from a gradient input so they can be
+ * changed with /set_gradient. "mode" is clamp (the default), wrap or mirror,
+ * and "space" is what the stops are blended in: rgb (the default), linear,
+ * hsv or oklab. The color goes to "o" as 8-bit sRGB, to "rcolor" as linear
+ * light for an output stage (see OutputStage), or both.
+ */
+#[derive(Clone)]
+pub struct Palette {
+    // Params
+    stops: Vec<Stop>,
+    mode: Mode,
+    space: Space,
+
+    // Inputs
+    i_idx: usize,
+    gradient_idx: Option<usize>,
+
+    // Outputs
+    o_idx: Option<usize>,
+    rcolor_idx: Option<usize>,
+}
+
+impl Palette {
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        let input_obj = dict.obj("inputs")?;
+
+        let i_idx = input_obj.index("i", VarType::Scalar)?;
+        let gradient_idx = input_obj.opt_index("gradient", VarType::Gradient)?;
+
+        let mut stops = Vec::new();
+        let mut mode = Mode::Clamp;
+        let mut space = Space::Rgb;
+        if dict.opt("params").is_some() {
+            let param_obj = dict.obj("params")?;
+
+            match (param_obj.opt("map"), param_obj.opt("stops")) {
+                (Some(_), None) => {
+                    let map = param_obj.str("map")?;
+                    stops = gradient::builtin(map)
+                        .ok_or_else(|| {
+                            param_obj.err(
+                                "map",
+                                format!(
+                                    "unknown map '{map}', expected one of {}",
+                                    BUILTIN_NAMES.join(", ")
+                                ),
+                            )
+                        })?
+                        .to_vec();
+                }
+                (None, Some(v)) => {
+                    stops = Gradient::from_obj(v, &param_obj.key_path("stops"))?.stops;
+                }
+                (Some(_), Some(_)) => {
+                    return Err(param_obj.err("stops", "expected only one of map or stops"))
+                }
+                (None, None) => (),
+            }
+
+            if param_obj.opt("mode").is_some() {
+                let name = param_obj.str("mode")?;
+                mode = Mode::from_name(name)
+                    .ok_or_else(|| param_obj.err("mode", "expected 'clamp', 'wrap' or 'mirror'"))?;
+            }
+            if param_obj.opt("space").is_some() {
+                let name = param_obj.str("space")?;
+                space = Space::from_name(name).ok_or_else(|| {
+                    param_obj.err("space", "expected 'rgb', 'linear', 'hsv' or 'oklab'")
+                })?;
+            }
+        }
+
+        match (stops.is_empty(), gradient_idx.is_some()) {
+            (true, false) => {
+                return Err(dict.err("params", "expected a map, stops or a gradient input"))
+            }
+            (false, true) => {
+                return Err(input_obj.err("gradient", "map or stops are given in params"))
+            }
+            _ => (),
+        }
+
+        let output_obj = dict.obj("outputs")?;
+
+        let o_idx = output_obj.opt_index("o", VarType::Color)?;
+        let rcolor_idx = output_obj.opt_index("rcolor", VarType::RColor)?;
+        if o_idx.is_none() && rcolor_idx.is_none() {
+            return Err(output_obj.err("o", "expected an o or rcolor output"));
+        }
+
+        Ok(Palette {
+            stops,
+            mode,
+            space,
+            i_idx,
+            gradient_idx,
+            o_idx,
+            rcolor_idx,
+        })
+    }
+}
+
+impl RenderBlock for Palette {
+    fn execute(&mut self, state: &mut RenderState) {
+        let val = state.get_scalar(self.i_idx);
+
+        let stops = match self.gradient_idx {
+            Some(idx) => &state.get_gradient(idx).stops,
+            None => &self.stops,
+        };
+        let c = gradient::sample(stops, val, self.mode, self.space);
+
+        if let Some(idx) = self.o_idx {
+            let [r, g, b] = [c.r, c.g, c.b].map(|x| (x * 255.0).round().clamp(0.0, 255.0) as u8);
+            state.set_color(idx, Color { r, g, b });
+        }
+        if let Some(idx) = self.rcolor_idx {
+            state.set_rcolor(idx, gradient::to_linear(c));
+        }
+    }
+
+    fn inputs(&self) -> Vec<Port> {
+        let mut ports = vec![Port::scalar("i", self.i_idx)];
+        if let Some(idx) = self.gradient_idx {
+            ports.push(Port::new("gradient", VarType::Gradient, idx));
+        }
+        ports
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        let mut ports = Vec::new();
+        if let Some(idx) = self.o_idx {
+            ports.push(Port::new("o", VarType::Color, idx));
+        }
+        if let Some(idx) = self.rcolor_idx {
+            ports.push(Port::new("rcolor", VarType::RColor, idx));
+        }
+        ports
+    }
+
+    fn purity(&self) -> Purity {
+        Purity::Pure
+    }
+}
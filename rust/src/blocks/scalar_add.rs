@@ -1,6 +1,8 @@
-use crate::render_block::{RenderBlock, RenderState};
-use json::JsonValue;
+use crate::config::{ConfigError, ConfigObj};
+use crate::render_block::{Port, Purity, RenderBlock, RenderState};
+use crate::var_types::VarType;
 
+#[derive(Clone)]
 pub struct ScalarAdd {
     // Inputs
     a_idx: usize,
@@ -11,39 +13,21 @@ pub struct ScalarAdd {
 }
 
 impl ScalarAdd {
-    pub fn from_obj(dict: &json::object::Object) -> Self {
-        let input_obj = match dict.get("inputs").expect("Missing input definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ScalarAdd inputs is not an object"),
-        };
-
-        let a_idx = input_obj
-            .get("a")
-            .expect("Missing a input")
-            .as_usize()
-            .expect("Could not parse a input");
-        let b_idx = input_obj
-            .get("b")
-            .expect("Missing b input")
-            .as_usize()
-            .expect("Could not parse b input");
-
-        let output_obj = match dict.get("outputs").expect("Missing output definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ScalarAdd outputs is not an object"),
-        };
-
-        let o_idx = output_obj
-            .get("o")
-            .expect("Missing o output")
-            .as_usize()
-            .expect("Could not parse o output");
-
-        ScalarAdd {
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        let input_obj = dict.obj("inputs")?;
+
+        let a_idx = input_obj.index("a", VarType::Scalar)?;
+        let b_idx = input_obj.index("b", VarType::Scalar)?;
+
+        let output_obj = dict.obj("outputs")?;
+
+        let o_idx = output_obj.index("o", VarType::Scalar)?;
+
+        Ok(ScalarAdd {
             a_idx,
             b_idx,
             o_idx,
-        }
+        })
     }
 }
 
@@ -54,4 +38,19 @@ impl RenderBlock for ScalarAdd {
             state.get_scalar(self.a_idx) + state.get_scalar(self.b_idx),
         );
     }
+
+    fn inputs(&self) -> Vec<Port> {
+        vec![
+            Port::scalar("a", self.a_idx),
+            Port::scalar("b", self.b_idx),
+        ]
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        vec![Port::scalar("o", self.o_idx)]
+    }
+
+    fn purity(&self) -> Purity {
+        Purity::Pure
+    }
 }
@@ -1,7 +1,8 @@
-use crate::render_block::{RenderBlock, RenderState};
-use crate::var_types::RealColor;
-use json::JsonValue;
+use crate::config::{ConfigError, ConfigObj};
+use crate::render_block::{Port, Purity, RenderBlock, RenderState};
+use crate::var_types::{RealColor, VarType};
 
+#[derive(Clone)]
 pub struct ScalarHsv2Rgb {
     // Inputs
 
@@ -15,45 +16,23 @@ pub struct ScalarHsv2Rgb {
 }
 
 impl ScalarHsv2Rgb {
-    pub fn from_obj(dict: &json::object::Object) -> Self {
-        let input_obj = match dict.get("inputs").expect("Missing input definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ScalarHsv2Rgb inputs is not an object"),
-        };
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        let input_obj = dict.obj("inputs")?;
 
-        let h_idx = input_obj
-            .get("h")
-            .expect("Missing h input")
-            .as_usize()
-            .expect("Could not parse h input");
-        let s_idx = input_obj
-            .get("s")
-            .expect("Missing s input")
-            .as_usize()
-            .expect("Could not parse s input");
-        let v_idx = input_obj
-            .get("v")
-            .expect("Missing v input")
-            .as_usize()
-            .expect("Could not parse v input");
-
-        let output_obj = match dict.get("outputs").expect("Missing output definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ScalarHsv2Rgb outputs is not an object"),
-        };
+        let h_idx = input_obj.index("h", VarType::Scalar)?;
+        let s_idx = input_obj.index("s", VarType::Scalar)?;
+        let v_idx = input_obj.index("v", VarType::Scalar)?;
+
+        let output_obj = dict.obj("outputs")?;
 
-        let o_idx = output_obj
-            .get("o")
-            .expect("Missing o output")
-            .as_usize()
-            .expect("Could not parse o output");
+        let o_idx = output_obj.index("o", VarType::RColor)?;
 
-        ScalarHsv2Rgb {
+        Ok(ScalarHsv2Rgb {
             h_idx,
             s_idx,
             v_idx,
             o_idx,
-        }
+        })
     }
 }
 
@@ -83,4 +62,20 @@ impl RenderBlock for ScalarHsv2Rgb {
 
         state.set_rcolor(self.o_idx, rcolor);
     }
+
+    fn inputs(&self) -> Vec<Port> {
+        vec![
+            Port::scalar("h", self.h_idx),
+            Port::scalar("s", self.s_idx),
+            Port::scalar("v", self.v_idx),
+        ]
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        vec![Port::new("o", VarType::RColor, self.o_idx)]
+    }
+
+    fn purity(&self) -> Purity {
+        Purity::Pure
+    }
 }
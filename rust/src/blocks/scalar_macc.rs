@@ -1,6 +1,8 @@
-use crate::render_block::{RenderBlock, RenderState};
-use json::JsonValue;
+use crate::config::{ConfigError, ConfigObj};
+use crate::render_block::{Port, Purity, RenderBlock, RenderState};
+use crate::var_types::VarType;
 
+#[derive(Clone)]
 pub struct ScalarMacc {
     // Inputs
     m_idxs: Vec<usize>,
@@ -11,52 +13,25 @@ pub struct ScalarMacc {
 }
 
 impl ScalarMacc {
-    pub fn from_obj(dict: &json::object::Object) -> Self {
-        let input_obj = match dict.get("inputs").expect("Missing input definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ScalarMacc inputs is not an object"),
-        };
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        let input_obj = dict.obj("inputs")?;
 
-        let m_array = match input_obj.get("m").expect("Missing m inputs") {
-            JsonValue::Array(x) => x,
-            _ => panic!("M input must be array"),
-        };
+        let m_idxs = input_obj.index_array("m", VarType::Scalar)?;
+        let x_idxs = input_obj.index_array("x", VarType::Scalar)?;
 
-        let x_array = match input_obj.get("x").expect("Missing x inputs") {
-            JsonValue::Array(x) => x,
-            _ => panic!("X input must be array"),
-        };
-
-        if m_array.len() != x_array.len() {
-            panic!("M and X inputs must be the same length")
-        }
-
-        let mut m_idxs = Vec::<usize>::with_capacity(m_array.len());
-        for m in m_array {
-            m_idxs.push(m.as_usize().expect("Could not parse m index"));
+        if m_idxs.len() != x_idxs.len() {
+            return Err(input_obj.err("x", "m and x inputs must be the same length"));
         }
 
-        let mut x_idxs = Vec::<usize>::with_capacity(m_array.len());
-        for x in x_array {
-            x_idxs.push(x.as_usize().expect("Could not parse x index"));
-        }
-
-        let output_obj = match dict.get("outputs").expect("Missing output definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ScalarMacc outputs is not an object"),
-        };
+        let output_obj = dict.obj("outputs")?;
 
-        let o_idx = output_obj
-            .get("o")
-            .expect("Missing o output")
-            .as_usize()
-            .expect("Could not parse o output");
+        let o_idx = output_obj.index("o", VarType::Scalar)?;
 
-        ScalarMacc {
+        Ok(ScalarMacc {
             m_idxs,
             x_idxs,
             o_idx,
-        }
+        })
     }
 }
 
@@ -68,4 +43,18 @@ impl RenderBlock for ScalarMacc {
         }
         state.set_scalar(self.o_idx, out);
     }
+
+    fn inputs(&self) -> Vec<Port> {
+        let mut ports = Port::scalars("m", &self.m_idxs);
+        ports.extend(Port::scalars("x", &self.x_idxs));
+        ports
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        vec![Port::scalar("o", self.o_idx)]
+    }
+
+    fn purity(&self) -> Purity {
+        Purity::Pure
+    }
 }
@@ -1,6 +1,8 @@
-use crate::render_block::{RenderBlock, RenderState};
-use json::JsonValue;
+use crate::config::{ConfigError, ConfigObj};
+use crate::render_block::{Port, Purity, RenderBlock, RenderState};
+use crate::var_types::VarType;
 
+#[derive(Clone)]
 pub struct ScalarRamp {
     // Inputs
     f_idx: usize,
@@ -14,57 +16,31 @@ pub struct ScalarRamp {
 }
 
 impl ScalarRamp {
-    pub fn from_obj(dict: &json::object::Object) -> Self {
-        let input_obj = match dict.get("inputs").expect("Missing input definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ScalarRamp inputs is not an object"),
-        };
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        let input_obj = dict.obj("inputs")?;
 
-        let f_idx = input_obj
-            .get("f")
-            .expect("Missing frequency input")
-            .as_usize()
-            .expect("Could not parse frequency input");
-        let min_idx = input_obj
-            .get("min")
-            .expect("Missing min input")
-            .as_usize()
-            .expect("Could not parse min input");
-        let max_idx = input_obj
-            .get("max")
-            .expect("Missing max input")
-            .as_usize()
-            .expect("Could not parse max input");
-        let i_idx = input_obj
-            .get("i")
-            .expect("Missing i input")
-            .as_usize()
-            .expect("Could not parse i input");
+        let f_idx = input_obj.index("f", VarType::Scalar)?;
+        let min_idx = input_obj.index("min", VarType::Scalar)?;
+        let max_idx = input_obj.index("max", VarType::Scalar)?;
+        let i_idx = input_obj.index("i", VarType::Scalar)?;
 
-        let output_obj = match dict.get("outputs").expect("Missing output definition") {
-            JsonValue::Object(x) => x,
-            _ => panic!("Initialization for ScalarRamp outputs is not an object"),
-        };
+        let output_obj = dict.obj("outputs")?;
 
-        let o_idx = output_obj
-            .get("o")
-            .expect("Missing o output")
-            .as_usize()
-            .expect("Could not parse o output");
+        let o_idx = output_obj.index("o", VarType::Scalar)?;
 
-        ScalarRamp {
+        Ok(ScalarRamp {
             f_idx,
             min_idx,
             max_idx,
             i_idx,
             o_idx,
-        }
+        })
     }
 }
 
 impl RenderBlock for ScalarRamp {
     fn execute(&mut self, state: &mut RenderState) {
-        // ramp function of frequency f
+        // ramp function of frequency f, in Hz when i is the elapsed time
         let phase = state.get_scalar(self.i_idx) * state.get_scalar(self.f_idx);
 
         let min = state.get_scalar(self.min_idx);
@@ -74,4 +50,21 @@ impl RenderBlock for ScalarRamp {
 
         state.set_scalar(self.o_idx, out);
     }
+
+    fn inputs(&self) -> Vec<Port> {
+        vec![
+            Port::scalar("f", self.f_idx),
+            Port::scalar("min", self.min_idx),
+            Port::scalar("max", self.max_idx),
+            Port::scalar("i", self.i_idx),
+        ]
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        vec![Port::scalar("o", self.o_idx)]
+    }
+
+    fn purity(&self) -> Purity {
+        Purity::Pure
+    }
 }
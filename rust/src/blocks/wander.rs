@@ -0,0 +1,262 @@
+use std::fmt;
+
+use crate::config::{ConfigError, ConfigObj};
+use crate::render_block::{Port, Purity, RenderBlock, RenderState};
+use crate::var_types::VarType;
+
+/*
+ * A value that drifts around a range at random, slowing as it nears the
+ * edges. Each call to step() advances it by one frame and step_accel() gives
+ * it a new random push.
+ */
+#[derive(Clone)]
+pub struct Wander {
+    accel_to_ctr: f32,
+    temp: f32,
+    decel: f32,
+
+    bound_ctr: f32,
+    bound_radius: f32,
+
+    // Position and velocity are always for a 2x2 box centered at the origin
+    pos: f32,
+    vel: f32,
+    accel: f32,
+}
+
+impl Wander {
+    pub fn new(min: f32, max: f32, val: f32) -> Self {
+        let radius = (max - min) / 2.0;
+        let center = (max + min) / 2.0;
+        let pos = (val - center) / radius;
+
+        Self {
+            accel_to_ctr: 0.01,
+            temp: 0.003,
+            decel: 0.9,
+            bound_ctr: center,
+            bound_radius: radius,
+            pos,
+            vel: 0.0,
+            accel: 0.0,
+        }
+    }
+
+    pub fn with_temp(mut self, temp: f32) -> Self {
+        self.temp = temp;
+        self
+    }
+
+    pub fn step_accel(&mut self, rng: &mut fastrand::Rng) {
+        /*
+         * Velocity change consists of:
+         * - deceleration towards stopped
+         * - acceleration towards center
+         * - random impulse
+         */
+        self.accel = self.decel * self.accel + (rng.f32() * 2.0 - 1.0) * self.temp;
+    }
+
+    pub fn step(&mut self) {
+        self.vel = -self.accel_to_ctr * self.pos.powi(5) + self.accel;
+
+        self.pos += self.vel;
+    }
+
+    pub fn get_pos(&self) -> f32 {
+        self.bound_radius * self.pos + self.bound_ctr
+    }
+}
+
+impl fmt::Display for Wander {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", self.pos, self.get_pos())
+    }
+}
+
+/* Several independent Wanders, one per dimension */
+#[derive(Clone)]
+pub struct WanderN {
+    wanderers: Vec<Wander>,
+}
+
+impl WanderN {
+    pub fn new(dim: usize, min: &[f32], max: &[f32], val: &[f32]) -> Self {
+        let wanderers = (0..dim)
+            .map(|i| Wander::new(min[i], max[i], val[i]))
+            .collect();
+
+        WanderN { wanderers }
+    }
+
+    pub fn with_temp(mut self, temp: f32) -> Self {
+        self.wanderers = self
+            .wanderers
+            .into_iter()
+            .map(|w| w.with_temp(temp))
+            .collect();
+        self
+    }
+
+    pub fn step_accel(&mut self, rng: &mut fastrand::Rng) {
+        self.wanderers.iter_mut().for_each(|w| w.step_accel(rng));
+    }
+
+    pub fn step(&mut self) {
+        self.wanderers.iter_mut().for_each(|w| w.step());
+    }
+
+    pub fn get_pos(&self) -> Vec<f32> {
+        self.wanderers.iter().map(|w| w.get_pos()).collect()
+    }
+}
+
+impl fmt::Display for WanderN {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: Vec<String> = self.wanderers.iter().map(|w| w.to_string()).collect();
+        write!(f, "{}", s.join(", "))
+    }
+}
+
+/* Frames between random pushes, as in the Waves animation */
+const DEFAULT_PERIOD: usize = 20;
+
+/* Frames to catch up at most, e.g. after the frame counter jumps */
+const MAX_CATCH_UP: u64 = 100;
+
+/*
+ * A WanderN as a block, e.g.
+ *   {"type": "wander",
+ *    "params": {"min": [-0.3, 0.0], "max": [0.3, 118.0], "temperature": 0.003},
+ *    "outputs": {"o": ["coeff", "offset"]}}
+ *
+ * It advances once for each new value of the frame counter, however many
+ * times it runs in a frame. Render threads each run their own copy, so the
+ * random pushes come from a seeded generator that every copy shares. "start"
+ * gives the initial values, which must lie within min and max, otherwise
+ * they're picked at random, as is the seed unless given.
+ */
+#[derive(Clone)]
+pub struct WanderBlock {
+    // Params
+    period: u64,
+
+    wander: WanderN,
+    rng: fastrand::Rng,
+    frame: Option<u64>,
+
+    // Outputs
+    o_idxs: Vec<usize>,
+}
+
+impl WanderBlock {
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        let param_obj = dict.obj("params")?;
+
+        let f32_array = |key| -> Result<Vec<f32>, ConfigError> {
+            param_obj
+                .array(key)?
+                .iter()
+                .map(|v| {
+                    v.as_f32()
+                        .ok_or_else(|| param_obj.err(key, "expected numbers"))
+                })
+                .collect()
+        };
+
+        let min = f32_array("min")?;
+        let max = f32_array("max")?;
+        if min.len() != max.len() {
+            return Err(param_obj.err("max", "min and max must be the same length"));
+        }
+        if min.iter().zip(&max).any(|(lo, hi)| lo >= hi) {
+            return Err(param_obj.err("max", "each max must be above its min"));
+        }
+
+        let mut rng = match param_obj.opt("seed") {
+            Some(_) => fastrand::Rng::with_seed(param_obj.usize("seed")? as u64),
+            None => fastrand::Rng::new(),
+        };
+
+        let start = match param_obj.opt("start") {
+            Some(_) => {
+                let start = f32_array("start")?;
+                if start.len() != min.len() {
+                    return Err(param_obj.err("start", "start must be the same length as min"));
+                }
+                let inside = |(v, (lo, hi)): (&f32, (&f32, &f32))| (lo..=hi).contains(&v);
+                if !start.iter().zip(min.iter().zip(&max)).all(inside) {
+                    return Err(param_obj.err("start", "each start must be within its min and max"));
+                }
+                start
+            }
+            None => min
+                .iter()
+                .zip(&max)
+                .map(|(lo, hi)| lo + rng.f32() * (hi - lo))
+                .collect(),
+        };
+
+        let mut wander = WanderN::new(min.len(), &min, &max, &start);
+        if param_obj.opt("temperature").is_some() {
+            wander = wander.with_temp(param_obj.f32("temperature")?);
+        }
+
+        let period = match param_obj.opt("period") {
+            Some(_) => param_obj.usize("period")?.max(1) as u64,
+            None => DEFAULT_PERIOD as u64,
+        };
+
+        let output_obj = dict.obj("outputs")?;
+
+        let o_idxs = output_obj.index_array("o", VarType::Scalar)?;
+        if o_idxs.len() != min.len() {
+            return Err(output_obj.err("o", "expected one output for each min and max"));
+        }
+
+        Ok(WanderBlock {
+            period,
+            wander,
+            rng,
+            frame: None,
+            o_idxs,
+        })
+    }
+}
+
+impl RenderBlock for WanderBlock {
+    fn execute(&mut self, state: &mut RenderState) {
+        let frame = state.frame() as u64;
+
+        // The first frame only sets where the counter starts
+        let steps = match self.frame {
+            Some(last) if frame > last => (frame - last).min(MAX_CATCH_UP),
+            _ => 0,
+        };
+        for f in frame - steps + 1..=frame {
+            if f % self.period == 0 {
+                self.wander.step_accel(&mut self.rng);
+            }
+            self.wander.step();
+        }
+        self.frame = Some(frame);
+
+        for (idx, pos) in self.o_idxs.iter().zip(self.wander.get_pos()) {
+            state.set_scalar(*idx, pos);
+        }
+    }
+
+    /* The frame counter is read from the state rather than a scalar */
+    fn inputs(&self) -> Vec<Port> {
+        Vec::new()
+    }
+
+    fn outputs(&self) -> Vec<Port> {
+        Port::scalars("o", &self.o_idxs)
+    }
+
+    /* It steps with the frame counter rather than per call */
+    fn purity(&self) -> Purity {
+        Purity::PerFrame
+    }
+}
@@ -0,0 +1,207 @@
+use std::fmt;
+
+use json::JsonValue;
+
+use crate::var_types::{FromJson, VarNames, VarType};
+
+/*
+ * An error found while interpreting a JSON configuration. The path locates
+ * the offending value, e.g. `primitives[3].inputs.f`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub path: String,
+    pub msg: String,
+}
+
+impl ConfigError {
+    pub fn new(path: &str, msg: impl Into<String>) -> Self {
+        ConfigError {
+            path: path.to_string(),
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.msg)
+        } else {
+            write!(f, "{}: {}", self.path, self.msg)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/* Path of a member of the object at `path` */
+pub fn key_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+/* Path of an element of the array at `path` */
+pub fn elem_path(path: &str, i: usize) -> String {
+    format!("{path}[{i}]")
+}
+
+/*
+ * A JSON object along with its location in the configuration. Accessors
+ * return a ConfigError naming the full path of whatever was missing or
+ * malformed. If variable names are attached, variable references may be
+ * given by name as well as by index.
+ */
+#[derive(Clone)]
+pub struct ConfigObj<'a> {
+    dict: &'a json::object::Object,
+    path: String,
+    names: Option<&'a VarNames>,
+}
+
+impl<'a> ConfigObj<'a> {
+    pub fn new(v: &'a JsonValue, path: &str) -> Result<Self, ConfigError> {
+        match v {
+            JsonValue::Object(dict) => Ok(Self::from_dict(dict, path)),
+            _ => Err(ConfigError::new(path, "expected an object")),
+        }
+    }
+
+    pub fn from_dict(dict: &'a json::object::Object, path: &str) -> Self {
+        ConfigObj {
+            dict,
+            path: path.to_string(),
+            names: None,
+        }
+    }
+
+    /* Allow variable references in this object and its children to use names */
+    pub fn with_names(mut self, names: &'a VarNames) -> Self {
+        self.names = Some(names);
+        self
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn dict(&self) -> &'a json::object::Object {
+        self.dict
+    }
+
+    pub fn key_path(&self, key: &str) -> String {
+        key_path(&self.path, key)
+    }
+
+    pub fn err(&self, key: &str, msg: impl Into<String>) -> ConfigError {
+        ConfigError::new(&self.key_path(key), msg)
+    }
+
+    pub fn opt(&self, key: &str) -> Option<&'a JsonValue> {
+        self.dict.get(key)
+    }
+
+    pub fn value(&self, key: &str) -> Result<&'a JsonValue, ConfigError> {
+        self.dict.get(key).ok_or_else(|| self.err(key, "missing"))
+    }
+
+    pub fn obj(&self, key: &str) -> Result<ConfigObj<'a>, ConfigError> {
+        let child = ConfigObj::new(self.value(key)?, &self.key_path(key))?;
+        Ok(match self.names {
+            Some(names) => child.with_names(names),
+            None => child,
+        })
+    }
+
+    pub fn array(&self, key: &str) -> Result<&'a [JsonValue], ConfigError> {
+        match self.value(key)? {
+            JsonValue::Array(x) => Ok(x),
+            _ => Err(self.err(key, "expected an array")),
+        }
+    }
+
+    pub fn str(&self, key: &str) -> Result<&'a str, ConfigError> {
+        self.value(key)?
+            .as_str()
+            .ok_or_else(|| self.err(key, "expected a string"))
+    }
+
+    pub fn f32(&self, key: &str) -> Result<f32, ConfigError> {
+        self.value(key)?
+            .as_f32()
+            .ok_or_else(|| self.err(key, "expected a number"))
+    }
+
+    pub fn usize(&self, key: &str) -> Result<usize, ConfigError> {
+        self.value(key)?
+            .as_usize()
+            .ok_or_else(|| self.err(key, "expected a non-negative integer"))
+    }
+
+    pub fn usize_array(&self, key: &str) -> Result<Vec<usize>, ConfigError> {
+        let path = self.key_path(key);
+        self.array(key)?
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                v.as_usize().ok_or_else(|| {
+                    ConfigError::new(&elem_path(&path, i), "expected a non-negative integer")
+                })
+            })
+            .collect()
+    }
+
+    pub fn list<T: FromJson>(&self, key: &str) -> Result<Vec<T>, ConfigError> {
+        let path = self.key_path(key);
+        self.array(key)?
+            .iter()
+            .enumerate()
+            .map(|(i, v)| T::from_obj(v, &elem_path(&path, i)))
+            .collect()
+    }
+
+    /* The index of a variable declared with `name` in the vars stanza */
+    pub fn named(&self, ty: VarType, name: &str) -> Option<usize> {
+        self.names.and_then(|names| names.get(ty, name))
+    }
+
+    /* Resolves a variable reference, either an index or a name */
+    fn resolve(&self, v: &JsonValue, ty: VarType, path: &str) -> Result<usize, ConfigError> {
+        if let Some(name) = v.as_str() {
+            return self
+                .names
+                .and_then(|names| names.get(ty, name))
+                .ok_or_else(|| ConfigError::new(path, format!("no {} named '{name}'", ty.name())));
+        }
+
+        v.as_usize().ok_or_else(|| {
+            ConfigError::new(path, "expected a variable name or non-negative index")
+        })
+    }
+
+    /* The index of the variable of type `ty` referenced by `key` */
+    pub fn index(&self, key: &str, ty: VarType) -> Result<usize, ConfigError> {
+        self.resolve(self.value(key)?, ty, &self.key_path(key))
+    }
+
+    /* Like index(), for an input or output a block can do without */
+    pub fn opt_index(&self, key: &str, ty: VarType) -> Result<Option<usize>, ConfigError> {
+        match self.opt(key) {
+            Some(v) => self.resolve(v, ty, &self.key_path(key)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /* Like index(), for an array of references */
+    pub fn index_array(&self, key: &str, ty: VarType) -> Result<Vec<usize>, ConfigError> {
+        let path = self.key_path(key);
+        self.array(key)?
+            .iter()
+            .enumerate()
+            .map(|(i, v)| self.resolve(v, ty, &elem_path(&path, i)))
+            .collect()
+    }
+}
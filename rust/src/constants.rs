@@ -8,11 +8,14 @@ pub const STRING_COUNT: usize = 46;
 pub const PIXEL_COUNT: usize = LED_COUNT * STRING_COUNT;
 pub const BYTES_PER_LED: usize = 3;
 
-pub const FRAME_SIZE_BYTES: usize = (PIXEL_COUNT * BYTES_PER_LED) as usize;
+pub const FRAME_SIZE_BYTES: usize = PIXEL_COUNT * BYTES_PER_LED;
 pub const FRAME_SIZE_WORDS: usize = FRAME_SIZE_BYTES / 2;
 
 // The ratio of Y distance to X distance. Multiply X axes by this to square up images
 pub const X_SCALE: f32 = 1.0 / 2.15;
+
+/* The largest image ImageLookup can index, in either direction */
+pub const MAX_IMAGE_SIDE: usize = 1024;
 /*
  * This returns the base index of the first color associated with these
  * logical position in the image.
@@ -24,13 +27,11 @@ pub const X_SCALE: f32 = 1.0 / 2.15;
 pub fn fb_idx(x: usize, y: usize) -> usize {
     let y_fb = y / 2;
 
-    let x_fb: usize;
-
-    if y % 2 == 0 {
-        x_fb = x;
+    let x_fb = if y.is_multiple_of(2) {
+        x
     } else {
-        x_fb = 2 * LED_COUNT - 1 - x;
-    }
+        2 * LED_COUNT - 1 - x
+    };
     let fb_idx = (y_fb + x_fb * STRING_COUNT / 2) * BYTES_PER_LED;
     //print!("-> {x_fb},{y_fb} = {fb_idx}\n");
 
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use hyper_tungstenite::{tungstenite::Message, HyperWebsocket};
+use json::JsonValue;
+use tokio::sync::broadcast::{error::RecvError, Sender};
+use tokio::sync::watch;
+
+use crate::config::{ConfigError, ConfigObj};
+use crate::modular_msg::{check_names, ModularMessage, VarId};
+use crate::var_types::*;
+
+/*
+ * The control WebSocket at /control/ws. Each text message from the client is
+ * one set request, or a batch of them:
+ *   {"set": "scalar", "name": "hue_speed", "value": 0.02, "seq": 7}
+ *   {"batch": [{"set": "scalar", "index": 4, "value": 1.0}, ...], "seq": 8}
+ * where "set" is one of scalar, position, color, rcolor, data or gradient and the value
+ * takes the same form as the matching /set_* endpoint. A batch is applied as
+ * a whole before the next frame, and a malformed batch changes nothing. So
+ * does one naming a variable the running config doesn't have.
+ *
+ * Every request is answered with {"ack": seq} or {"ack": seq, "error": ...},
+ * seq being null if the request didn't have one. Every change made through
+ * any interface, including this one, is pushed to the client as an event:
+ *   {"event": "scalar", "name": "hue_speed", "value": 0.02}
+ *   {"event": "config"}
+ *   {"event": "batch", "events": [...]}
+ * Data events leave out the value since it can be large.
+ */
+
+/* A single request or a batch, with its names and indices checked against `names` */
+fn parse_message(
+    v: &JsonValue,
+    names: &Option<watch::Receiver<VarNames>>,
+) -> Result<ModularMessage, ConfigError> {
+    let dict = ConfigObj::new(v, "")?;
+    let (msg, path) = match dict.opt("batch") {
+        Some(_) => (ModularMessage::batch_from_obj(&dict, "batch")?, "batch"),
+        None => (ModularMessage::set_from_obj(v, "")?, ""),
+    };
+    check_names(names, &msg, path)?;
+    Ok(msg)
+}
+
+/* Sends one client message on to the render loop and builds its ack */
+fn handle_text(
+    text: &str,
+    mod_cmd: &Sender<ModularMessage>,
+    names: &Option<watch::Receiver<VarNames>>,
+) -> JsonValue {
+    let v = match json::parse(text) {
+        Ok(v) => v,
+        Err(why) => return json::object! {ack: null, error: why.to_string()},
+    };
+
+    let mut ack = json::object! {ack: v["seq"].clone()};
+    let sent = parse_message(&v, names)
+        .map_err(|why| why.to_string())
+        .and_then(|msg| mod_cmd.send(msg).map_err(|why| why.to_string()));
+    if let Err(why) = sent {
+        ack["error"] = why.into();
+    }
+
+    ack
+}
+
+fn id_event(kind: &str, id: &VarId) -> JsonValue {
+    let mut event = json::object! {event: kind};
+    match id {
+        VarId::Index(i) => event["index"] = (*i).into(),
+        VarId::Name(name) => event["name"] = name.clone().into(),
+    }
+    event
+}
+
+fn set_event<T: ToJson>(kind: &str, id: &VarId, value: &T) -> JsonValue {
+    let mut event = id_event(kind, id);
+    event["value"] = value.to_json();
+    event
+}
+
+/* Describes a message that went to the render loop */
+fn event(msg: &ModularMessage) -> JsonValue {
+    match msg {
+        ModularMessage::Config(_) => json::object! {event: "config"},
+        ModularMessage::SetScalar(v) => set_event("scalar", &v.id, &v.value),
+        ModularMessage::SetPosition(v) => set_event("position", &v.id, &v.value),
+        ModularMessage::SetColor(v) => set_event("color", &v.id, &v.value),
+        ModularMessage::SetRColor(v) => set_event("rcolor", &v.id, &v.value),
+        ModularMessage::SetData(v) => id_event("data", &v.id),
+        ModularMessage::SetGradient(v) => set_event("gradient", &v.id, &v.value),
+        ModularMessage::Batch(msgs) => {
+            json::object! {event: "batch", events: msgs.iter().map(event).collect::<Vec<_>>()}
+        }
+    }
+}
+
+/* Serves a single client until either side goes away */
+pub async fn control_ws(
+    ws: HyperWebsocket,
+    mod_cmd: Arc<Sender<ModularMessage>>,
+    names: Option<watch::Receiver<VarNames>>,
+) {
+    let mut ws = match ws.await {
+        Ok(ws) => ws,
+        Err(why) => {
+            println!("Control WebSocket handshake failed: {why}");
+            return;
+        }
+    };
+
+    let mut events = mod_cmd.subscribe();
+
+    loop {
+        let reply = tokio::select! {
+            msg = ws.next() => match msg {
+                Some(Ok(Message::Text(text))) => handle_text(&text, &mod_cmd, &names),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => continue,
+            },
+            msg = events.recv() => match msg {
+                Ok(msg) => event(&msg),
+                // Let the client know it may be out of date
+                Err(RecvError::Lagged(n)) => json::object! {event: "lagged", missed: n},
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        if ws.send(Message::text(reply.dump())).await.is_err() {
+            break;
+        }
+    }
+}
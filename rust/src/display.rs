@@ -1,3 +1,4 @@
+use clap::ValueEnum;
 use memmap::{MmapMut, MmapOptions};
 use nix::ioctl_write_int_bad;
 use std::cell::{Cell, RefCell, RefMut};
@@ -9,6 +10,155 @@ use std::thread::sleep;
 use std::time::{Duration, Instant};
 
 use crate::constants;
+use crate::sim_display::{SimDisplay, SimWhiteLed};
+
+/*
+ * Anything that can drive the white LED channels. This is split out of
+ * DisplayBackend because the LED task runs independently of the render loop
+ * and only needs the register, not the framebuffer.
+ */
+pub trait WhiteLed {
+    fn set_white_led(&self, cold: u8, cool: u8, hot: u8);
+
+    fn set_white_led_f32(&self, cold: f32, cool: f32, hot: f32) {
+        const LED_MAX: f32 = 127.0; // MSB being ignored? TODO
+        let x = (cold * LED_MAX).round().clamp(0.0, LED_MAX) as u8;
+        let y = (cool * LED_MAX).round().clamp(0.0, LED_MAX) as u8;
+        let z = (hot * LED_MAX).round().clamp(0.0, LED_MAX) as u8;
+        self.set_white_led(x, y, z)
+    }
+}
+
+/*
+ * The interface the render loops use to produce frames. The framebuffer is
+ * in native (BRG, fb_idx) order and must not be borrowed across a flush().
+ */
+pub trait DisplayBackend: WhiteLed {
+    fn read_id(&self) -> u16;
+    fn empty_count(&self) -> usize;
+    fn borrow_fb(&self) -> RefMut<'_, [u8]>;
+    fn flush(&self);
+
+    /* The total amount of time spent waiting for the FIFO to flush */
+    fn wait_time(&self) -> Duration;
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayKind {
+    /* The FPGA framebuffer via /dev/mem and /dev/ledfb */
+    Fpga,
+    /* In-memory framebuffer for development without hardware */
+    Sim,
+}
+
+impl std::fmt::Display for DisplayKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+impl DisplayKind {
+    pub fn open(self, sim_fps: f32) -> Box<dyn DisplayBackend> {
+        match self {
+            DisplayKind::Fpga => Box::new(LedDisplay::new()),
+            DisplayKind::Sim => Box::new(SimDisplay::new(sim_fps)),
+        }
+    }
+
+    pub fn open_white_led(self) -> Box<dyn WhiteLed + Send> {
+        match self {
+            DisplayKind::Fpga => Box::new(LedRegs::new()),
+            DisplayKind::Sim => Box::new(SimWhiteLed::new()),
+        }
+    }
+}
+
+/* Reports the display's ID and FIFO state, and clears the framebuffer */
+pub fn start_display(disp: &dyn DisplayBackend) {
+    let id = disp.read_id();
+
+    println!("FPGA ID: 0x{:x}", id);
+    println!("Starting empty count: {}", disp.empty_count());
+
+    disp.borrow_fb().fill(0);
+}
+
+/* Prints where a render loop's time went, `start` being when it began */
+pub fn print_loop_stats(
+    disp: &dyn DisplayBackend,
+    frames: u32,
+    start: Instant,
+    render_time: Duration,
+    pace_time: Duration,
+) {
+    println!(
+        "{} frames in {:?}. Spent {:?} rendering, {:?} in flush and {:?} pacing.",
+        frames,
+        start.elapsed(),
+        render_time,
+        disp.wait_time(),
+        pace_time
+    );
+}
+
+/*
+ * Holds a render loop to a target frame rate by sleeping after each flush.
+ * Without one, flush() is what paces the loop and wait() returns at once.
+ */
+pub struct FramePacer {
+    period: Option<Duration>,
+    next_frame: Instant,
+    /* Time spent sleeping, for print_loop_stats */
+    pub pace_time: Duration,
+}
+
+impl FramePacer {
+    pub fn new(fps: f32) -> Self {
+        FramePacer {
+            period: (fps > 0.0).then(|| Duration::from_secs_f32(1.0 / fps)),
+            next_frame: Instant::now(),
+            pace_time: Duration::ZERO,
+        }
+    }
+
+    /* Sleeps until the next frame is due */
+    pub fn wait(&mut self) {
+        let Some(period) = self.period else {
+            return;
+        };
+
+        self.next_frame += period;
+        let pace_start = Instant::now();
+        if self.next_frame > pace_start {
+            sleep(self.next_frame - pace_start);
+            self.pace_time += pace_start.elapsed();
+        } else {
+            // Running behind, so don't try to catch up with a burst of frames
+            self.next_frame = pace_start;
+        }
+    }
+}
+
+/* Blanks the display after the last frame and waits for the FIFO to drain */
+pub fn blank_display(disp: &dyn DisplayBackend) {
+    // Wait for last frame to flush
+    sleep(Duration::from_millis(5));
+    disp.read_id();
+
+    // Blank
+    disp.borrow_fb().fill(0);
+    disp.flush();
+    // Wait for DMA to finish. Otherwise the last blank frame doesn't get flushed.
+    sleep(Duration::from_millis(5));
+
+    while disp.empty_count() < 8000 {
+        sleep(Duration::from_micros(100));
+    }
+    println!("Ending empty count: {}", disp.empty_count());
+}
 
 pub struct LedRegs {
     /*
@@ -39,7 +189,7 @@ pub struct LedDisplay {
     fb: LedFramebuffer,
 
     /* The total amount of time spent waiting for the FIFO to flush */
-    pub wait_time: Cell<Duration>,
+    wait_time: Cell<Duration>,
 }
 
 const FB_IOC: u32 = 0;
@@ -87,19 +237,14 @@ impl LedRegs {
         unsafe { read_volatile(&(*self.regs).empty_count) as usize }
     }
 
-    pub fn set_white_led(&self, cold: u8, cool: u8, hot: u8) -> () {
+}
+
+impl WhiteLed for LedRegs {
+    fn set_white_led(&self, cold: u8, cool: u8, hot: u8) {
         let value = (cold as u32) << 8 | (cool as u32) | (hot as u32) << 16;
         //print!("cold {cold} cool {cool} hot {hot} val 0x{value:06x}\n");
         unsafe { write_volatile(&mut (*self.regs).white_led, value) }
     }
-
-    pub fn set_white_led_f32(&self, cold: f32, cool: f32, hot: f32) -> () {
-        const LED_MAX: f32 = 127.0; // MSB being ignored? TODO
-        let x = (cold * LED_MAX).round().clamp(0.0, LED_MAX) as u8;
-        let y = (cool * LED_MAX).round().clamp(0.0, LED_MAX) as u8;
-        let z = (hot * LED_MAX).round().clamp(0.0, LED_MAX) as u8;
-        self.set_white_led(x, y, z)
-    }
 }
 
 impl LedFramebuffer {
@@ -129,7 +274,7 @@ impl LedFramebuffer {
 
 
     // MmapMut has same lifetime as LedDisplay
-    pub fn borrow_fb<'a>(&'a self) -> RefMut<[u8]> {
+    pub fn borrow_fb(&self) -> RefMut<'_, [u8]> {
         let mut_fb = self.fb_cell.borrow_mut();
         let (begin, mut _end) = RefMut::map_split(mut_fb, |slice| {
             slice.split_at_mut(constants::FRAME_SIZE_BYTES)
@@ -138,7 +283,7 @@ impl LedFramebuffer {
         begin
     }
 
-    pub fn flush(&self) -> () {
+    pub fn flush(&self) {
         let fd = self.f_fb.as_raw_fd();
 
         unsafe {
@@ -157,20 +302,28 @@ impl LedDisplay {
             wait_time,
         }
     }
+}
 
-    pub fn read_id(&self) -> u16 {
+impl WhiteLed for LedDisplay {
+    fn set_white_led(&self, cold: u8, cool: u8, hot: u8) {
+        self.regs.set_white_led(cold, cool, hot)
+    }
+}
+
+impl DisplayBackend for LedDisplay {
+    fn read_id(&self) -> u16 {
         self.regs.read_id()
     }
 
-    pub fn empty_count(&self) -> usize {
+    fn empty_count(&self) -> usize {
         self.regs.empty_count()
     }
 
-    pub fn borrow_fb<'a>(&'a self) -> RefMut<[u8]> {
+    fn borrow_fb(&self) -> RefMut<'_, [u8]> {
         self.fb.borrow_fb()
     }
 
-    pub fn flush(&self) -> () {
+    fn flush(&self) {
         // TODO: Remove me once FIFO overruns are resolved
         sleep(Duration::from_millis(2));
 
@@ -181,4 +334,8 @@ impl LedDisplay {
         self.wait_time.set(self.wait_time.get() + now.elapsed());
         self.fb.flush();
     }
+
+    fn wait_time(&self) -> Duration {
+        self.wait_time.get()
+    }
 }
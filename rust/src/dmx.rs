@@ -0,0 +1,321 @@
+use std::fmt;
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+
+use clap::ValueEnum;
+use json::JsonValue;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::Sender;
+
+use crate::config::{ConfigError, ConfigObj};
+use crate::constants;
+use crate::modular_msg::{ModularMessage, VarId, VarMsg};
+use crate::var_types::FromJson;
+
+/* Whole pixels per universe, leaving the last two channels unused */
+pub const PIXELS_PER_UNIVERSE: usize = 170;
+/* Universes needed to cover the grid */
+pub const FRAME_UNIVERSES: usize = constants::PIXEL_COUNT.div_ceil(PIXELS_PER_UNIVERSE);
+/* Art-Net universes are 15 bits, and sACN is kept to the same range */
+pub const UNIVERSE_COUNT: usize = 0x8000;
+/* The last universe a frame can start on and still fit */
+pub const MAX_FIRST_UNIVERSE: u16 = (UNIVERSE_COUNT - FRAME_UNIVERSES) as u16;
+
+const ARTNET_PORT: u16 = 6454;
+const ARTNET_ID: &[u8] = b"Art-Net\0";
+const ARTNET_OP_DMX: u16 = 0x5000;
+
+const SACN_PORT: u16 = 5568;
+const SACN_ID: &[u8] = b"ASC-E1.17\0\0\0";
+const SACN_VECTOR_ROOT_DATA: u32 = 0x04;
+const SACN_VECTOR_FRAMING_DATA: u32 = 0x02;
+const SACN_DMX_START: usize = 126;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum DmxProtocol {
+    Artnet,
+    Sacn,
+}
+
+impl fmt::Display for DmxProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DmxProtocol::Artnet => write!(f, "artnet"),
+            DmxProtocol::Sacn => write!(f, "sacn"),
+        }
+    }
+}
+
+/* The DMX levels for one universe. Channel 1 is data[0]. */
+#[derive(Debug, PartialEq)]
+pub struct DmxPacket<'a> {
+    pub universe: u16,
+    pub data: &'a [u8],
+}
+
+fn be16(b: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([b[at], b[at + 1]])
+}
+
+fn be32(b: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+/* An ArtDmx packet. Anything else, e.g. ArtPoll, is ignored. */
+fn parse_artnet(b: &[u8]) -> Option<DmxPacket<'_>> {
+    if b.len() < 18 || !b.starts_with(ARTNET_ID) {
+        return None;
+    }
+    if u16::from_le_bytes([b[8], b[9]]) != ARTNET_OP_DMX {
+        return None;
+    }
+
+    // SubUni then Net make up the 15-bit port address
+    let universe = u16::from_le_bytes([b[14], b[15]]) & 0x7fff;
+    let len = (be16(b, 16) as usize).min(b.len() - 18);
+
+    Some(DmxPacket {
+        universe,
+        data: &b[18..18 + len],
+    })
+}
+
+/* An E1.31 data packet with the null start code. Sync packets are ignored. */
+fn parse_sacn(b: &[u8]) -> Option<DmxPacket<'_>> {
+    if b.len() < SACN_DMX_START || &b[4..16] != SACN_ID {
+        return None;
+    }
+    if be32(b, 18) != SACN_VECTOR_ROOT_DATA || be32(b, 40) != SACN_VECTOR_FRAMING_DATA {
+        return None;
+    }
+
+    // The property count includes the start code
+    let count = be16(b, 123) as usize;
+    if b[125] != 0 || count == 0 {
+        return None;
+    }
+    let len = (count - 1).min(b.len() - SACN_DMX_START);
+
+    Some(DmxPacket {
+        universe: be16(b, 113),
+        data: &b[SACN_DMX_START..SACN_DMX_START + len],
+    })
+}
+
+impl DmxProtocol {
+    fn port(self) -> u16 {
+        match self {
+            DmxProtocol::Artnet => ARTNET_PORT,
+            DmxProtocol::Sacn => SACN_PORT,
+        }
+    }
+
+    pub fn parse(self, b: &[u8]) -> Option<DmxPacket<'_>> {
+        match self {
+            DmxProtocol::Artnet => parse_artnet(b),
+            DmxProtocol::Sacn => parse_sacn(b),
+        }
+    }
+
+    /*
+     * Binds the protocol's port on all interfaces. sACN senders usually
+     * multicast each universe to its own group, so join those as well.
+     */
+    async fn bind(self, universes: impl Iterator<Item = u16>) -> std::io::Result<UdpSocket> {
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], self.port()))).await?;
+
+        if self == DmxProtocol::Sacn {
+            // Linux allows 20 groups per socket by default, so this can fail
+            let mut failed = Vec::new();
+            for u in universes {
+                let [hi, lo] = u.to_be_bytes();
+                let group = Ipv4Addr::new(239, 255, hi, lo);
+                if let Err(why) = socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED) {
+                    failed.push(format!("{u} ({why})"));
+                }
+            }
+            if !failed.is_empty() {
+                println!(
+                    "Couldn't join the multicast groups for universes {}; send those unicast",
+                    failed.join(", ")
+                );
+            }
+        }
+
+        println!("Listening for {self} on port {}", self.port());
+        Ok(socket)
+    }
+}
+
+/*
+ * Receives whole frames spread over FRAME_UNIVERSES consecutive universes,
+ * PIXELS_PER_UNIVERSE RGB pixels each, in the same order as /set_data. Each
+ * frame is sent on as SetData once its last universe arrives.
+ */
+pub async fn dmx_pixels_main(proto: DmxProtocol, first: u16, mod_cmd: Sender<ModularMessage>) {
+    let Some(end) = first.checked_add(FRAME_UNIVERSES as u16) else {
+        println!("A frame starting at universe {first} would run past the last universe");
+        return;
+    };
+    let universes = first..end;
+    let socket = match proto.bind(universes.clone()).await {
+        Ok(s) => s,
+        Err(why) => {
+            println!("Couldn't listen for {proto}: {why}");
+            return;
+        }
+    };
+
+    let mut frame = vec![0u8; constants::FRAME_SIZE_BYTES];
+    let mut buf = [0u8; 1024];
+    loop {
+        let len = match socket.recv(&mut buf).await {
+            Ok(len) => len,
+            Err(why) => {
+                println!("DMX receive failure: {why}");
+                continue;
+            }
+        };
+
+        let Some(packet) = proto.parse(&buf[..len]) else {
+            continue;
+        };
+        if !universes.contains(&packet.universe) {
+            continue;
+        }
+
+        let start = (packet.universe - first) as usize * PIXELS_PER_UNIVERSE * 3;
+        let end = (start + PIXELS_PER_UNIVERSE * 3).min(frame.len());
+        let n = packet.data.len().min(end - start);
+        frame[start..start + n].copy_from_slice(&packet.data[..n]);
+
+        if packet.universe == universes.end - 1 {
+            let msg = ModularMessage::SetData(VarMsg {
+                id: VarId::Index(0),
+                value: frame.clone(),
+            });
+            if let Err(why) = mod_cmd.send(msg) {
+                println!("Failed to send DMX frame: {why}");
+            }
+        }
+    }
+}
+
+/*
+ * One scalar driven by a DMX channel, e.g.
+ *   {"channel": 1, "name": "hue_speed", "min": 0.0, "max": 0.05}
+ * Channels count from 1. Levels 0-255 map linearly onto min-max, which
+ * default to 0 and 1.
+ */
+#[derive(Debug, Clone)]
+struct ScalarChannel {
+    channel: usize,
+    id: VarId,
+    min: f32,
+    max: f32,
+}
+
+impl FromJson for ScalarChannel {
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        let dict = ConfigObj::new(v, path)?;
+
+        let channel = dict.usize("channel")?;
+        if !(1..=512).contains(&channel) {
+            return Err(dict.err("channel", "expected a DMX channel from 1 to 512"));
+        }
+
+        let opt_f32 = |key| match dict.opt(key) {
+            Some(_) => dict.f32(key).map(Some),
+            None => Ok(None),
+        };
+
+        Ok(ScalarChannel {
+            channel,
+            id: VarId::from_obj(&dict)?,
+            min: opt_f32("min")?.unwrap_or(0.0),
+            max: opt_f32("max")?.unwrap_or(1.0),
+        })
+    }
+}
+
+/* A map file: {"universe": 1, "channels": [...]} */
+pub struct ScalarMap {
+    universe: u16,
+    channels: Vec<ScalarChannel>,
+}
+
+impl ScalarMap {
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let s = fs::read_to_string(path).map_err(|why| ConfigError::new("", why.to_string()))?;
+        let v = json::parse(&s).map_err(|why| ConfigError::new("", why.to_string()))?;
+        let dict = ConfigObj::new(&v, "")?;
+
+        let universe = dict.usize("universe")?;
+        let universe = u16::try_from(universe)
+            .ok()
+            .filter(|u| usize::from(*u) < UNIVERSE_COUNT)
+            .ok_or_else(|| dict.err("universe", "expected a universe below 32768"))?;
+
+        Ok(ScalarMap {
+            universe,
+            channels: dict.list("channels")?,
+        })
+    }
+}
+
+/*
+ * Drives scalars from one universe. Each packet that changes any mapped level
+ * is sent on as a single batch, so related channels land in the same frame.
+ */
+pub async fn dmx_scalars_main(proto: DmxProtocol, map: ScalarMap, mod_cmd: Sender<ModularMessage>) {
+    let socket = match proto.bind(std::iter::once(map.universe)).await {
+        Ok(s) => s,
+        Err(why) => {
+            println!("Couldn't listen for {proto}: {why}");
+            return;
+        }
+    };
+
+    // Nothing has been sent yet, so the first packet always goes through
+    let mut levels: Vec<Option<u8>> = vec![None; map.channels.len()];
+    let mut buf = [0u8; 1024];
+    loop {
+        let len = match socket.recv(&mut buf).await {
+            Ok(len) => len,
+            Err(why) => {
+                println!("DMX receive failure: {why}");
+                continue;
+            }
+        };
+
+        let Some(packet) = proto.parse(&buf[..len]) else {
+            continue;
+        };
+        if packet.universe != map.universe {
+            continue;
+        }
+
+        let mut batch = Vec::new();
+        for (ch, last) in map.channels.iter().zip(levels.iter_mut()) {
+            let Some(&level) = packet.data.get(ch.channel - 1) else {
+                continue;
+            };
+            if *last == Some(level) {
+                continue;
+            }
+            *last = Some(level);
+
+            let value = ch.min + (ch.max - ch.min) * f32::from(level) / 255.0;
+            batch.push(ModularMessage::SetScalar(VarMsg {
+                id: ch.id.clone(),
+                value,
+            }));
+        }
+
+        if !batch.is_empty() {
+            if let Err(why) = mod_cmd.send(ModularMessage::Batch(batch)) {
+                println!("Failed to send DMX levels: {why}");
+            }
+        }
+    }
+}
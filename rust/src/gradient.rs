@@ -0,0 +1,311 @@
+use json::JsonValue;
+
+use crate::config::{elem_path, ConfigError, ConfigObj};
+use crate::var_types::{FromJson, RealColor, ToJson};
+
+/* A color at a position along a gradient */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stop {
+    pub pos: f32,
+    pub color: RealColor,
+}
+
+pub const fn stop(pos: f32, r: f32, g: f32, b: f32) -> Stop {
+    Stop {
+        pos,
+        color: RealColor { r, g, b },
+    }
+}
+
+/* The same, from 8-bit channels */
+const fn stop8(pos: f32, r: u8, g: u8, b: u8) -> Stop {
+    stop(pos, r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+}
+
+/*
+ * The color maps from the Waves animation. They were written for a -1 to 1
+ * ramp in framebuffer order, so these are the same stops moved to 0-1 in RGB.
+ */
+const RAINBOW: &[Stop] = &[
+    stop8(0.0, 4, 4, 16),
+    stop8(1.0 / 3.0, 16, 4, 4),
+    stop8(2.0 / 3.0, 4, 16, 4),
+    stop8(1.0, 4, 4, 16),
+];
+const ELITE: &[Stop] = &[
+    stop8(0.0, 0, 0, 0),
+    stop8(0.6, 0, 0, 0),
+    stop8(1.0, 24, 4, 0),
+];
+const SKY: &[Stop] = &[
+    stop8(0.0, 0, 0, 24),
+    stop8(0.1, 0, 0, 24),
+    stop8(0.3, 16, 16, 16),
+    stop8(0.75, 4, 4, 4),
+    stop8(1.0, 1, 1, 1),
+];
+
+pub const BUILTIN_NAMES: [&str; 3] = ["rainbow", "elite", "sky"];
+
+pub fn builtin(name: &str) -> Option<&'static [Stop]> {
+    match name {
+        "rainbow" => Some(RAINBOW),
+        "elite" => Some(ELITE),
+        "sky" => Some(SKY),
+        _ => None,
+    }
+}
+
+/* What happens past the first and last stops */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /* The end colors carry on */
+    Clamp,
+    /* The gradient repeats */
+    Wrap,
+    /* The gradient repeats, every other copy reversed */
+    Mirror,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "clamp" => Some(Mode::Clamp),
+            "wrap" => Some(Mode::Wrap),
+            "mirror" => Some(Mode::Mirror),
+            _ => None,
+        }
+    }
+
+    /* Brings t into [lo, hi] */
+    fn apply(self, t: f32, lo: f32, hi: f32) -> f32 {
+        let span = hi - lo;
+        if !t.is_finite() {
+            return lo;
+        }
+        if span <= 0.0 {
+            return t.clamp(lo, hi);
+        }
+
+        match self {
+            Mode::Clamp => t.clamp(lo, hi),
+            Mode::Wrap => lo + (t - lo).rem_euclid(span),
+            Mode::Mirror => {
+                let u = (t - lo).rem_euclid(2.0 * span);
+                lo + if u > span { 2.0 * span - u } else { u }
+            }
+        }
+    }
+}
+
+/* The space colors are blended in between stops */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Space {
+    Rgb,
+    /* Undoes the sRGB curve first, so blends keep their brightness */
+    LinearRgb,
+    /* Hue takes the shorter way around */
+    Hsv,
+    /* Perceptually even blends */
+    Oklab,
+}
+
+impl Space {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgb" => Some(Space::Rgb),
+            "linear" => Some(Space::LinearRgb),
+            "hsv" => Some(Space::Hsv),
+            "oklab" => Some(Space::Oklab),
+            _ => None,
+        }
+    }
+
+    fn encode(self, c: RealColor) -> [f32; 3] {
+        match self {
+            Space::Rgb => [c.r, c.g, c.b],
+            Space::LinearRgb => [c.r, c.g, c.b].map(srgb_to_linear),
+            Space::Hsv => rgb_to_hsv([c.r, c.g, c.b]),
+            Space::Oklab => linear_to_oklab([c.r, c.g, c.b].map(srgb_to_linear)),
+        }
+    }
+
+    fn decode(self, v: [f32; 3]) -> RealColor {
+        let [r, g, b] = match self {
+            Space::Rgb => v,
+            Space::LinearRgb => v.map(linear_to_srgb),
+            Space::Hsv => hsv_to_rgb(v),
+            Space::Oklab => oklab_to_linear(v).map(linear_to_srgb),
+        };
+        RealColor { r, g, b }
+    }
+
+    fn lerp(self, a: [f32; 3], b: [f32; 3], alpha: f32) -> [f32; 3] {
+        let mut v = [0.0; 3];
+        for c in 0..3 {
+            v[c] = a[c] + (b[c] - a[c]) * alpha;
+        }
+        if self == Space::Hsv {
+            let dh = (b[0] - a[0] + 0.5).rem_euclid(1.0) - 0.5;
+            v[0] = (a[0] + dh * alpha).rem_euclid(1.0);
+        }
+        v
+    }
+}
+
+/*
+ * The color at t along stops sorted by position. Stops at the same position
+ * give a hard edge.
+ */
+pub fn sample(stops: &[Stop], t: f32, mode: Mode, space: Space) -> RealColor {
+    let (Some(first), Some(last)) = (stops.first(), stops.last()) else {
+        return RealColor::default();
+    };
+    let t = mode.apply(t, first.pos, last.pos);
+
+    // The first stop past t, so this pair of stops brackets it
+    let i = stops.partition_point(|s| s.pos <= t);
+    if i == 0 {
+        return first.color;
+    }
+    if i == stops.len() {
+        return last.color;
+    }
+    let (a, b) = (&stops[i - 1], &stops[i]);
+
+    let alpha = (t - a.pos) / (b.pos - a.pos);
+    let v = space.lerp(space.encode(a.color), space.encode(b.color), alpha);
+    space.decode(v)
+}
+
+/* Stops are given in sRGB, this is the same color as linear light */
+pub fn to_linear(c: RealColor) -> RealColor {
+    RealColor {
+        r: srgb_to_linear(c.r),
+        g: srgb_to_linear(c.g),
+        b: srgb_to_linear(c.b),
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/* Hue, saturation and value, all 0 to 1 */
+fn rgb_to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let d = max - min;
+
+    let h = if d <= 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / d).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / d + 2.0
+    } else {
+        (r - g) / d + 4.0
+    };
+    let s = if max > 0.0 { d / max } else { 0.0 };
+
+    [h / 6.0, s, max]
+}
+
+fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let hp = h.rem_euclid(1.0) * 6.0;
+    let c = v * s;
+    let x = c * (1.0 - (hp.rem_euclid(2.0) - 1.0).abs());
+    let m = v - c;
+
+    let [r, g, b] = match hp as u8 {
+        0 => [c, x, 0.0],
+        1 => [x, c, 0.0],
+        2 => [0.0, c, x],
+        3 => [0.0, x, c],
+        4 => [x, 0.0, c],
+        _ => [c, 0.0, x],
+    };
+    [r + m, g + m, b + m]
+}
+
+/* From https://bottosson.github.io/posts/oklab/ */
+fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+fn oklab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = (l + 0.396_337_78 * a + 0.215_803_76 * b).powi(3);
+    let m_ = (l - 0.105_561_346 * a - 0.063_854_17 * b).powi(3);
+    let s_ = (l - 0.089_484_18 * a - 1.291_485_5 * b).powi(3);
+
+    [
+        4.076_741_7 * l_ - 3.307_711_6 * m_ + 0.230_969_94 * s_,
+        -1.268_438 * l_ + 2.609_757_4 * m_ - 0.341_319_38 * s_,
+        -0.0041960863 * l_ - 0.703_418_6 * m_ + 1.707_614_7 * s_,
+    ]
+}
+
+/*
+ * Stops in a config or the gradient pool of the vars stanza, as
+ *   [{"pos": 0.0, "color": {"r": 0.1, "g": 0.0, "b": 0.4}}, ...]
+ * with positions in order and channels from 0 to 1.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Gradient {
+    pub stops: Vec<Stop>,
+}
+
+impl FromJson for Gradient {
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        if !v.is_array() {
+            return Err(ConfigError::new(path, "expected an array of stops"));
+        }
+
+        let mut stops: Vec<Stop> = Vec::new();
+        for (i, s) in v.members().enumerate() {
+            let dict = ConfigObj::new(s, &elem_path(path, i))?;
+
+            let pos = dict.f32("pos")?;
+            let color = RealColor::from_obj(dict.value("color")?, &dict.key_path("color"))?;
+            if stops.last().is_some_and(|last| pos < last.pos) {
+                return Err(dict.err("pos", "stops must be in order of position"));
+            }
+            stops.push(Stop { pos, color });
+        }
+
+        if stops.is_empty() {
+            return Err(ConfigError::new(path, "expected at least one stop"));
+        }
+        Ok(Gradient { stops })
+    }
+}
+
+impl ToJson for Gradient {
+    fn to_json(&self) -> JsonValue {
+        self.stops
+            .iter()
+            .map(|s| json::object! {pos: s.pos.to_json(), color: s.color.to_json()})
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashSet;
+
+use crate::render_block::{Purity, RenderBlock, SCALAR_X, SCALAR_Y};
+use crate::var_types::VarType;
+
+type Slot = (VarType, usize);
+
+/*
+ * A block list split by how often each block needs to run. Frame blocks run
+ * once before the pixel loop and pixel blocks run for every pixel, each list
+ * keeping the order of the original config.
+ *
+ * `serial` is set when a pixel's output can depend on the pixels rendered
+ * before it, i.e. a pixel block is stateful or reads a slot before it (or a
+ * later pixel block) writes it. Such graphs have to be rendered in order on
+ * one thread.
+ */
+pub struct Graph {
+    pub frame_blocks: Vec<Box<dyn RenderBlock>>,
+    pub pixel_blocks: Vec<Box<dyn RenderBlock>>,
+    pub serial: bool,
+}
+
+/*
+ * Works out which blocks can be hoisted out of the pixel loop without
+ * changing the output. A block has to stay per-pixel if:
+ * - it is stateful, since it expects to run once per pixel
+ * - it reads x or y, or anything written by a per-pixel block
+ * - it writes a slot that a per-pixel block also writes
+ * - it reads a slot before a later block writes it, since the first pixel of
+ *   a frame would see a different value from the rest
+ * The last three are applied until nothing changes. PerFrame blocks keep state
+ * too, but only step once per frame, so they are hoisted like pure ones.
+ */
+pub fn compile(blocks: Vec<Box<dyn RenderBlock>>) -> Graph {
+    let inputs: Vec<Vec<Slot>> = blocks
+        .iter()
+        .map(|b| b.inputs().iter().map(|p| (p.ty, p.index)).collect())
+        .collect();
+    let outputs: Vec<Vec<Slot>> = blocks
+        .iter()
+        .map(|b| b.outputs().iter().map(|p| (p.ty, p.index)).collect())
+        .collect();
+
+    let mut per_pixel: Vec<bool> = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            let stale_read = inputs[i]
+                .iter()
+                .any(|s| outputs[i..].iter().any(|o| o.contains(s)));
+            b.purity() == Purity::Stateful || stale_read
+        })
+        .collect();
+
+    loop {
+        let mut varying: HashSet<Slot> =
+            HashSet::from([(VarType::Scalar, SCALAR_X), (VarType::Scalar, SCALAR_Y)]);
+        for (i, outs) in outputs.iter().enumerate() {
+            if per_pixel[i] {
+                varying.extend(outs.iter().copied());
+            }
+        }
+
+        let mut changed = false;
+        for i in 0..blocks.len() {
+            if per_pixel[i] {
+                continue;
+            }
+
+            let depends = inputs[i].iter().any(|s| varying.contains(s));
+            let clobbered = outputs[i].iter().any(|s| varying.contains(s));
+            if depends || clobbered {
+                per_pixel[i] = true;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    let pixel_idxs: Vec<usize> = (0..blocks.len()).filter(|&i| per_pixel[i]).collect();
+    let serial = pixel_idxs.iter().enumerate().any(|(n, &i)| {
+        let carried = inputs[i]
+            .iter()
+            .any(|s| pixel_idxs[n..].iter().any(|&j| outputs[j].contains(s)));
+        blocks[i].purity() == Purity::Stateful || carried
+    });
+
+    let mut frame_blocks = Vec::new();
+    let mut pixel_blocks = Vec::new();
+    for (block, pixel) in blocks.into_iter().zip(per_pixel) {
+        if pixel {
+            pixel_blocks.push(block);
+        } else {
+            frame_blocks.push(block);
+        }
+    }
+
+    Graph {
+        frame_blocks,
+        pixel_blocks,
+        serial,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_block::{Port, RenderState, SCALAR_FRAME};
+
+    /* A block that only declares the scalars it reads and writes */
+    #[derive(Clone)]
+    struct Stub {
+        name: &'static str,
+        ins: Vec<usize>,
+        outs: Vec<usize>,
+        purity: Purity,
+    }
+
+    impl RenderBlock for Stub {
+        fn execute(&mut self, _state: &mut RenderState) {}
+
+        fn inputs(&self) -> Vec<Port> {
+            Port::scalars("i", &self.ins)
+        }
+
+        // Named after the block, so assert_stages() can tell them apart
+        fn outputs(&self) -> Vec<Port> {
+            self.outs
+                .iter()
+                .map(|i| Port::scalar(self.name, *i))
+                .collect()
+        }
+
+        fn purity(&self) -> Purity {
+            self.purity
+        }
+    }
+
+    fn stub(
+        name: &'static str,
+        ins: &[usize],
+        outs: &[usize],
+        purity: Purity,
+    ) -> Box<dyn RenderBlock> {
+        Box::new(Stub {
+            name,
+            ins: ins.to_vec(),
+            outs: outs.to_vec(),
+            purity,
+        })
+    }
+
+    fn pure(name: &'static str, ins: &[usize], outs: &[usize]) -> Box<dyn RenderBlock> {
+        stub(name, ins, outs, Purity::Pure)
+    }
+
+    /* Checks which blocks ended up in each stage */
+    fn assert_stages(graph: &Graph, frame: &[&str], pixel: &[&str]) {
+        let names = |blocks: &[Box<dyn RenderBlock>]| -> Vec<String> {
+            blocks.iter().map(|b| b.outputs()[0].name.clone()).collect()
+        };
+        assert_eq!(names(&graph.frame_blocks), frame);
+        assert_eq!(names(&graph.pixel_blocks), pixel);
+    }
+
+    #[test]
+    fn pure_blocks_are_hoisted() {
+        let graph = compile(vec![
+            pure("a", &[SCALAR_FRAME], &[3]),
+            pure("b", &[3], &[4]),
+            pure("c", &[SCALAR_X, 4], &[5]),
+        ]);
+        assert_stages(&graph, &["a", "b"], &["c"]);
+        assert!(!graph.serial);
+    }
+
+    #[test]
+    fn pixel_dependence_spreads() {
+        let graph = compile(vec![
+            pure("a", &[SCALAR_Y], &[3]),
+            pure("b", &[3], &[4]),
+            pure("c", &[SCALAR_FRAME], &[5]),
+        ]);
+        assert_stages(&graph, &["c"], &["a", "b"]);
+        assert!(!graph.serial);
+    }
+
+    #[test]
+    fn clobbered_slots_stay_per_pixel() {
+        // a would be hoisted, but b overwrites its output for every pixel
+        let graph = compile(vec![
+            pure("a", &[SCALAR_FRAME], &[3]),
+            pure("b", &[SCALAR_X], &[3]),
+        ]);
+        assert_stages(&graph, &[], &["a", "b"]);
+        assert!(!graph.serial);
+    }
+
+    #[test]
+    fn stale_reads_stay_per_pixel() {
+        let graph = compile(vec![
+            pure("a", &[4], &[3]),
+            pure("b", &[SCALAR_FRAME], &[4]),
+        ]);
+        assert_stages(&graph, &["b"], &["a"]);
+        assert!(!graph.serial);
+
+        // Within the pixel stage, a sees what b wrote for the previous pixel
+        let graph = compile(vec![pure("a", &[SCALAR_X, 4], &[3]), pure("b", &[3], &[4])]);
+        assert_stages(&graph, &[], &["a", "b"]);
+        assert!(graph.serial);
+    }
+
+    #[test]
+    fn stateful_blocks_force_serial() {
+        let graph = compile(vec![
+            pure("a", &[SCALAR_FRAME], &[3]),
+            stub("b", &[3], &[4], Purity::Stateful),
+        ]);
+        assert_stages(&graph, &["a"], &["b"]);
+        assert!(graph.serial);
+
+        // PerFrame blocks are hoisted and don't
+        let graph = compile(vec![
+            stub("a", &[], &[3], Purity::PerFrame),
+            pure("b", &[SCALAR_X, 3], &[4]),
+        ]);
+        assert_stages(&graph, &["a"], &["b"]);
+        assert!(!graph.serial);
+    }
+}
@@ -5,43 +5,29 @@ use tokio::sync::broadcast::Receiver;
 
 use interpolation::Lerp;
 
-use crate::display::LedRegs;
+use crate::display::WhiteLed;
+use crate::gradient::{self, stop, Mode, Space, Stop};
 use crate::led_msg::LedMessage;
 
-pub struct ColorPoint {
-    val: f32,
-    color: [f32; 3],
-}
-
-// Cold, cool, hot
-const COLOR_MAP : [ColorPoint; 5]= [
-    ColorPoint {val:    0.0, color: [1.0, 0.0, 0.0] },
-    ColorPoint {val: 1900.0, color: [1.0, 0.0, 0.0] },
-    ColorPoint {val: 3000.0, color: [0.0, 1.0, 0.0] },
-    ColorPoint {val: 6500.0, color: [0.0, 0.0, 1.0] },
-    ColorPoint {val: 9900.0, color: [0.0, 0.0, 1.0] },
+// Color temperature to cold, cool, hot as r, g, b
+const COLOR_MAP: [Stop; 5] = [
+    stop(   0.0, 1.0, 0.0, 0.0),
+    stop(1900.0, 1.0, 0.0, 0.0),
+    stop(3000.0, 0.0, 1.0, 0.0),
+    stop(6500.0, 0.0, 0.0, 1.0),
+    stop(9900.0, 0.0, 0.0, 1.0),
 ];
 
-pub async fn led_main(mut led_rx: Receiver<LedMessage>) {
-    /* Regmap initialization */
-
-    let regs = LedRegs::new();
+pub async fn led_main(regs: Box<dyn WhiteLed + Send>, mut led_rx: Receiver<LedMessage>) {
     let mut cur_color : [f32; 3] = [0.0, 0.0, 0.0];
 
     // Blocking wait to receive new message
     while let Ok(msg) = led_rx.recv().await {
         match msg {
             LedMessage::SetWhiteTemp(temp, value, delay) => {
-                // Find which pair of points this value falls between
-                //print!("{x},{y} {p}\n");
-                let i = COLOR_MAP.iter().position(|x| temp <= x.val).expect("{p} is outside of map limits") - 1;
-
-                // Scale the independent variable
-                //print!("Using index {i}, original value is {p}\n");
-                let alpha: f32 = (temp - COLOR_MAP[i].val)/(COLOR_MAP[i+1].val - COLOR_MAP[i].val);
-                // Perform the interpolation
-                //print!("alpha = {alpha}\n");
-                let new_color = COLOR_MAP[i].color.lerp(&COLOR_MAP[i+1].color, &alpha);
+                // Temperatures off either end of the map get its end colors
+                let c = gradient::sample(&COLOR_MAP, temp, Mode::Clamp, Space::Rgb);
+                let new_color = [c.r, c.g, c.b];
                 //print!("new_color: {new_color:?}\n");
                 let adj_value = value.powf(2.2);
                 let adj_color = [
@@ -1,122 +1,263 @@
-use std::thread::sleep;
+use std::thread;
 use std::time::{Duration, Instant};
 use std::vec::Vec;
 
-use tokio::sync;
-
+use chrono::Timelike;
 use json::JsonValue;
+use tokio::sync::{self, broadcast::error::TryRecvError};
 
 use crate::args::Args;
 use crate::blocks::block_factory;
+use crate::config::{elem_path, ConfigError, ConfigObj};
 use crate::constants;
-use crate::display::LedDisplay;
-use crate::modular_msg::ModularMessage;
+use crate::display::{blank_display, print_loop_stats, start_display, DisplayBackend, FramePacer};
+use crate::modular_msg::{ModularMessage, ModularQuery, VarId};
+use crate::output::OutputStage;
 use crate::render_block::{RenderBlock, RenderState};
+use crate::renderer::{FrameTime, Renderer};
+use crate::transition::Transition;
+use crate::validate::validate_blocks;
+use crate::var_types::{Color, VarNames, VarType};
+
+/* A config that has been checked and is ready to render */
+pub struct ParsedConfig {
+    pub state: RenderState,
+    pub blocks: Vec<Box<dyn RenderBlock>>,
+    pub output: OutputStage,
+    pub transition: Option<Transition>,
+    pub warnings: Vec<ConfigError>,
+}
+
+/* Builds and validates the render state and block list described by a config */
+pub fn parse_config(json_obj: &json::object::Object) -> Result<ParsedConfig, ConfigError> {
+    let dict = ConfigObj::from_dict(json_obj, "");
+
+    let state = RenderState::from_obj(dict.value("vars")?, &dict.key_path("vars"))?;
+
+    let path = dict.key_path("primitives");
+    let blocks = dict
+        .array("primitives")?
+        .iter()
+        .enumerate()
+        .map(|(i, b)| block_factory(b, &elem_path(&path, i), state.names()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let output = OutputStage::from_config(&dict, &state)?;
+    let warnings = validate_blocks(&state, &blocks, &output, &path)?;
+    let transition = Transition::from_config(&dict)?;
 
+    Ok(ParsedConfig {
+        state,
+        blocks,
+        output,
+        transition,
+        warnings,
+    })
+}
+
+/*
+ * Checks a config without keeping the result. Used by the server before
+ * sending. Returns any warnings on success.
+ */
+pub fn validate_config(json_obj: &json::object::Object) -> Result<Vec<ConfigError>, ConfigError> {
+    parse_config(json_obj).map(|cfg| cfg.warnings)
+}
+
+/* Returns whether the config was accepted */
 fn update_cfg(
-    json_obj: json::object::Object,
-    state: &mut RenderState,
-    blocks: &mut Vec<Box<dyn RenderBlock>>,
-) {
-    state.from_obj(json_obj.get("vars").expect("No vars stanza in JSON"));
-
-    let block_list = match json_obj
-        .get("primitives")
-        .expect("No primitives stanza in JSON")
-    {
-        JsonValue::Array(x) => x,
-        _ => panic!("Primitives stanza is not an array"),
-    };
+    json_obj: &json::object::Object,
+    renderer: &mut Renderer,
+    names: &sync::watch::Sender<VarNames>,
+) -> bool {
+    // Only replace the running config if the new one is entirely valid
+    match parse_config(json_obj) {
+        Ok(cfg) => {
+            renderer.set_graph(cfg.state, cfg.blocks, cfg.output, cfg.transition);
+            names.send_replace(renderer.state().names().clone());
+            let (frame_blocks, pixel_blocks) = renderer.stage_sizes();
+            println!("Config updated: {frame_blocks} per-frame and {pixel_blocks} per-pixel blocks");
+            if renderer.is_serial() {
+                println!("Pixels depend on earlier pixels, so rendering on one thread");
+            }
+            true
+        }
+        Err(why) => {
+            println!("Rejected config: {why}");
+            false
+        }
+    }
+}
 
-    blocks.clear();
-    for b in block_list {
-        blocks.push(block_factory(b));
+/*
+ * Applies a message from the server. `config` tracks the active config and
+ * `names` publishes its variable names.
+ */
+fn apply_message(
+    msg: ModularMessage,
+    renderer: &mut Renderer,
+    config: &mut Option<json::object::Object>,
+    names: &sync::watch::Sender<VarNames>,
+) {
+    let state = renderer.state_mut();
+    match msg {
+        ModularMessage::Config(json_obj) => {
+            if update_cfg(&json_obj, renderer, names) {
+                *config = Some(json_obj);
+            }
+        }
+        ModularMessage::SetScalar(v) => {
+            if let Some(i) = resolve(state, VarType::Scalar, &v.id) {
+                state.set_scalar(i, v.value)
+            }
+        }
+        ModularMessage::SetPosition(v) => {
+            if let Some(i) = resolve(state, VarType::Position, &v.id) {
+                state.set_position(i, v.value)
+            }
+        }
+        ModularMessage::SetColor(v) => {
+            if let Some(i) = resolve(state, VarType::Color, &v.id) {
+                state.set_color(i, v.value)
+            }
+        }
+        ModularMessage::SetRColor(v) => {
+            if let Some(i) = resolve(state, VarType::RColor, &v.id) {
+                state.set_rcolor(i, v.value)
+            }
+        }
+        ModularMessage::SetData(v) => {
+            if let Some(i) = resolve(state, VarType::Data, &v.id) {
+                state.set_data(i, v.value)
+            }
+        }
+        ModularMessage::SetGradient(v) => {
+            if let Some(i) = resolve(state, VarType::Gradient, &v.id) {
+                state.set_gradient(i, v.value)
+            }
+        }
+        ModularMessage::Batch(msgs) => {
+            for msg in msgs {
+                apply_message(msg, renderer, config, names);
+            }
+        }
     }
+}
 
-    println!("Config updated");
+fn answer_query(query: ModularQuery, config: Option<&json::object::Object>, state: &RenderState) {
+    // The requester may have given up waiting, which is fine
+    let _ = match query {
+        ModularQuery::Config(reply) => {
+            reply.send(config.map(|cfg| JsonValue::Object(cfg.clone())))
+        }
+        ModularQuery::Vars(reply) => reply.send(Some(state.to_json())),
+        ModularQuery::Var(ty, id, reply) => {
+            let value = match id {
+                VarId::Index(i) => Some(i),
+                VarId::Name(name) => state.names().get(ty, &name),
+            };
+            reply.send(value.and_then(|i| state.var_to_json(ty, i)))
+        }
+    };
 }
 
-pub fn fb_main(args: &Args, mut rx_cfg: sync::broadcast::Receiver<ModularMessage>) {
-    /* Framebuffer initialization */
-    let disp = LedDisplay::new();
-    let id = disp.read_id();
+/* Looks up the slot a set message refers to in the running config */
+fn resolve(state: &RenderState, ty: VarType, id: &VarId) -> Option<usize> {
+    match id {
+        VarId::Index(i) => Some(*i),
+        VarId::Name(name) => {
+            let idx = state.names().get(ty, name);
+            if idx.is_none() {
+                println!("No {} named '{name}' in the current config", ty.name());
+            }
+            idx
+        }
+    }
+}
 
-    println!("FPGA ID: 0x{:x}", id);
-    println!("Starting empty count: {}", disp.empty_count());
+pub fn fb_main(
+    args: &Args,
+    disp: &dyn DisplayBackend,
+    mut rx_cfg: sync::broadcast::Receiver<ModularMessage>,
+    mut rx_query: sync::mpsc::Receiver<ModularQuery>,
+    names: sync::watch::Sender<VarNames>,
+) {
+    start_display(disp);
 
-    let mut fb = disp.borrow_fb();
-    fb.fill(0);
+    let threads = match args.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    println!("Rendering with {threads} thread(s)");
 
-    let mut state = RenderState::new();
-    let mut blocks = Vec::<Box<dyn RenderBlock>>::new();
+    let mut renderer = Renderer::new(RenderState::new(), Vec::new(), threads);
+    // The last config accepted, for reporting back
+    let mut config = None;
+    let mut pixels = vec![Color::default(); constants::PIXEL_COUNT];
 
     let now = Instant::now();
+    let mut render_time = Duration::ZERO;
+    let mut pacer = FramePacer::new(args.fps);
+    let mut last_frame = now;
 
     let mut frame: u32 = 0;
     while args.frame_cnt == 0 || frame < args.frame_cnt {
         // Update config if there's anything new
-        while let Ok(msg) = rx_cfg.try_recv() {
-            //println!("Received {:?}", msg);
-            match msg {
-                ModularMessage::Config(json_obj) => update_cfg(json_obj, &mut state, &mut blocks),
-                ModularMessage::SetScalar(v) => state.set_scalar(v.index, v.value),
-                ModularMessage::SetPosition(v) => state.set_position(v.index, v.value),
-                ModularMessage::SetColor(v) => state.set_color(v.index, v.value),
-                ModularMessage::SetRColor(v) => state.set_rcolor(v.index, v.value),
-                ModularMessage::SetData(v) => state.set_data(v.index, v.value),
+        loop {
+            match rx_cfg.try_recv() {
+                Ok(msg) => apply_message(msg, &mut renderer, &mut config, &names),
+                // The oldest messages were dropped, but the rest are still queued
+                Err(TryRecvError::Lagged(n)) => println!("Missed {n} messages"),
+                Err(_) => break,
             }
         }
 
-        state.set_scalar(0, frame as f32);
-        for x in 0..constants::LED_COUNT {
-            state.set_scalar(1, x as f32);
-            for y in 0..constants::STRING_COUNT {
-                state.set_scalar(2, y as f32);
+        while let Ok(query) = rx_query.try_recv() {
+            answer_query(query, config.as_ref(), renderer.state());
+        }
+
+        let render_start = Instant::now();
+        let wall = chrono::Local::now();
+        let time = FrameTime {
+            frame,
+            elapsed: (render_start - now).as_secs_f64(),
+            dt: (render_start - last_frame).as_secs_f32(),
+            time_of_day: wall.num_seconds_from_midnight() as f32
+                + wall.nanosecond() as f32 * 1e-9,
+        };
+        last_frame = render_start;
 
-                for block in blocks.iter_mut() {
-                    block.as_mut().execute(&mut state);
-                }
+        renderer.render(&time, &mut pixels);
+        render_time += render_start.elapsed();
 
+        // The framebuffer can't be borrowed across a flush
+        let mut fb = disp.borrow_fb();
+        for (x, col) in pixels.chunks(constants::STRING_COUNT).enumerate() {
+            for (y, c) in col.iter().enumerate() {
                 let idx = constants::fb_idx(x, y);
 
-                let c = state.get_color(0);
-                fb[idx + 0] = c.b;
+                fb[idx] = c.b;
                 fb[idx + 1] = c.r;
                 fb[idx + 2] = c.g;
             }
         }
+        drop(fb);
+
         // Render:
         //anim.render(frame, &mut fb);
         // Call ioctl to DMA to hardware
         disp.flush();
 
         if args.debug {
-            state.debug();
+            renderer.state().debug();
             //break;
         }
 
+        pacer.wait();
+
         frame += 1;
     }
 
-    println!(
-        "{} frames in {:?}. Spent {:?} in flush.",
-        frame,
-        now.elapsed(),
-        disp.wait_time.get()
-    );
-
-    // Wait for last frame to flush
-    sleep(Duration::from_millis(5));
-    disp.read_id();
-
-    // Blank
-    fb.fill(0);
-    disp.flush();
-    // Wait for DMA to finish. Otherwise the last blank frame doesn't get flushed.
-    sleep(Duration::from_millis(5));
-
-    while disp.empty_count() < 8000 {
-        sleep(Duration::from_micros(100));
-    }
-    println!("Ending empty count: {}", disp.empty_count());
+    print_loop_stats(disp, frame, now, render_time, pacer.pace_time);
+
+    blank_display(disp);
 }
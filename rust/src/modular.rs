@@ -9,21 +9,39 @@ use clap::Parser;
 use json::JsonValue;
 
 use args::Args;
+use dmx::{dmx_scalars_main, ScalarMap};
 use led_ctrl::led_main;
-use mod_ctrl::fb_main;
-use modular_msg::ModularMessage;
+use mod_ctrl::{fb_main, validate_config};
+use modular_msg::{ModularMessage, RenderLink};
+use playlist::playlist_main;
+use preview::with_preview;
 use server::server_run;
+use var_types::VarNames;
 
 mod args;
 mod blocks;
+mod config;
 mod constants;
+mod control_ws;
 mod display;
+mod dmx;
+mod gradient;
+mod graph;
 mod led_ctrl;
 mod led_msg;
 mod mod_ctrl;
 mod modular_msg;
+mod output;
+mod player_msg;
+mod playlist;
+mod playlist_msg;
+mod preview;
 mod render_block;
+mod renderer;
 mod server;
+mod sim_display;
+mod transition;
+mod validate;
 mod var_types;
 
 fn init_config(args: &Args) -> json::object::Object {
@@ -57,18 +75,49 @@ fn main() {
         .build()
         .unwrap();
 
-    // Create the broadcast channel
+    // Create the broadcast channel. DMX and websocket clients can send many
+    // messages per frame, so the render loop needs room to catch up.
     let (led_cmd, led_rx) = sync::broadcast::channel(16);
-    let (mod_cmd, mod_rx) = sync::broadcast::channel(16);
+    let (mod_cmd, mod_rx) = sync::broadcast::channel(256);
     let server_mod_cmd = mod_cmd.clone();
+    let (query_cmd, query_rx) = sync::mpsc::channel(16);
+    let (names_tx, names_rx) = sync::watch::channel(VarNames::new());
+    let render_link = RenderLink {
+        query: query_cmd,
+        names: names_rx,
+    };
+
+    let (disp, preview_cmd) = with_preview(args.display.open(args.sim_fps), args.preview);
 
     let cfg = init_config(&args);
+    match validate_config(&cfg) {
+        Ok(warnings) => warnings
+            .iter()
+            .for_each(|w| println!("Config warning: {w}")),
+        Err(why) => panic!("Invalid config {}: {why}", args.json),
+    }
 
     if let Err(e) = mod_cmd.send(ModularMessage::Config(cfg)) {
-        print!("Error sending new config: {e}\n");
+        println!("Error sending new config: {e}");
     }
 
-    rt.spawn(server_run(server_mod_cmd, led_cmd));
-    rt.spawn(led_main(led_rx));
-    rt.block_on(async move { fb_main(&args, mod_rx) });
+    if let (Some(proto), Some(path)) = (args.dmx, &args.dmx_map) {
+        match ScalarMap::load(path) {
+            Ok(map) => {
+                rt.spawn(dmx_scalars_main(proto, map, mod_cmd.clone()));
+            }
+            Err(why) => panic!("Invalid DMX map {path}: {why}"),
+        }
+    }
+
+    // The playlist only runs if a schedule was given
+    let playlist_cmd = args.playlist.clone().map(|path| {
+        let (playlist_cmd, playlist_rx) = sync::mpsc::channel(16);
+        rt.spawn(playlist_main(path, mod_cmd.clone(), playlist_rx));
+        playlist_cmd
+    });
+
+    rt.spawn(server_run(server_mod_cmd, led_cmd, preview_cmd, Some(validate_config), playlist_cmd, Some(render_link), None));
+    rt.spawn(led_main(args.display.open_white_led(), led_rx));
+    rt.block_on(async move { fb_main(&args, disp.as_ref(), mod_rx, query_rx, names_tx) });
 }
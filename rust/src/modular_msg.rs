@@ -1,9 +1,42 @@
-use crate::var_types;
-use json;
+use json::JsonValue;
+use tokio::sync::{mpsc, oneshot, watch};
 
-#[derive(Default, Debug, Copy, Clone, PartialEq)]
+use crate::config::{elem_path, key_path, ConfigError, ConfigObj};
+use crate::gradient::Gradient;
+use crate::var_types::{self, FromJson, VarNames, VarType};
+
+/* Identifies a variable by its index in the pool or by its name in the config */
+#[derive(Debug, Clone, PartialEq)]
+pub enum VarId {
+    Index(usize),
+    Name(String),
+}
+
+impl VarId {
+    /* Reads whichever of "index" or "name" is present, preferring the index */
+    pub fn from_obj(dict: &ConfigObj) -> Result<Self, ConfigError> {
+        if dict.opt("index").is_some() {
+            Ok(VarId::Index(dict.usize("index")?))
+        } else if dict.opt("name").is_some() {
+            Ok(VarId::Name(dict.str("name")?.to_string()))
+        } else {
+            Err(dict.err("index", "missing index or name"))
+        }
+    }
+}
+
+impl std::fmt::Display for VarId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VarId::Index(i) => write!(f, "{i}"),
+            VarId::Name(name) => write!(f, "'{name}'"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct VarMsg<T> {
-    pub index: usize,
+    pub id: VarId,
     pub value: T,
 }
 
@@ -16,6 +49,134 @@ pub enum ModularMessage {
     SetColor(VarMsg<var_types::Color>),
     SetRColor(VarMsg<var_types::RealColor>),
     SetData(VarMsg<var_types::Data>),
+    SetGradient(VarMsg<Gradient>),
+
+    /* Applied in order, all before the next frame */
+    Batch(Vec<ModularMessage>),
+}
+
+/* Parses the variable and value of one set request */
+fn set_from_obj<T: FromJson + Settable>(dict: &ConfigObj) -> Result<ModularMessage, ConfigError> {
+    let id = VarId::from_obj(dict)?;
+    let value = T::from_obj(dict.value("value")?, &dict.key_path("value"))?;
+    Ok(T::into_message(id, value))
+}
+
+impl ModularMessage {
+    /*
+     * Parses a typed assignment such as
+     *   {"set": "scalar", "name": "hue_speed", "value": 0.02}
+     * where "set" is scalar, position, color, rcolor, data or gradient and the rest is
+     * what the matching /set_* endpoint takes.
+     */
+    pub fn set_from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        let dict = ConfigObj::new(v, path)?;
+        match dict.str("set")? {
+            "scalar" => set_from_obj::<f32>(&dict),
+            "position" => set_from_obj::<var_types::Position>(&dict),
+            "color" => set_from_obj::<var_types::Color>(&dict),
+            "rcolor" => set_from_obj::<var_types::RealColor>(&dict),
+            "data" => set_from_obj::<var_types::Data>(&dict),
+            "gradient" => set_from_obj::<Gradient>(&dict),
+            other => Err(dict.err("set", format!("unknown variable type '{other}'"))),
+        }
+    }
+
+    /* The pool and variable a set message assigns */
+    fn target(&self) -> Option<(VarType, &VarId)> {
+        match self {
+            ModularMessage::SetScalar(v) => Some((VarType::Scalar, &v.id)),
+            ModularMessage::SetPosition(v) => Some((VarType::Position, &v.id)),
+            ModularMessage::SetColor(v) => Some((VarType::Color, &v.id)),
+            ModularMessage::SetRColor(v) => Some((VarType::RColor, &v.id)),
+            ModularMessage::SetData(v) => Some((VarType::Data, &v.id)),
+            ModularMessage::SetGradient(v) => Some((VarType::Gradient, &v.id)),
+            ModularMessage::Config(_) | ModularMessage::Batch(_) => None,
+        }
+    }
+
+    /*
+     * Checks that every variable a set message, or each message of a batch,
+     * refers to is in `names`, by name or by an index within its pool, so that
+     * a request for a missing variable can be rejected rather than dropped by
+     * the render loop. `path` locates the message, or the batch's array, in
+     * the request.
+     */
+    pub fn check_names(&self, names: &VarNames, path: &str) -> Result<(), ConfigError> {
+        if let ModularMessage::Batch(msgs) = self {
+            return msgs
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, msg)| msg.check_names(names, &elem_path(path, i)));
+        }
+
+        match self.target() {
+            Some((ty, VarId::Name(name))) if names.get(ty, name).is_none() => {
+                Err(ConfigError::new(
+                    &key_path(path, "name"),
+                    format!("no {} named '{name}' in the current config", ty.name()),
+                ))
+            }
+            Some((ty, VarId::Index(i))) if *i >= names.len(ty) => {
+                Err(ConfigError::new(
+                    &key_path(path, "index"),
+                    format!(
+                        "no {} {i} in the current config, which has {}",
+                        ty.name(),
+                        names.len(ty)
+                    ),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /* A Batch of the assignments in the array at `key` */
+    pub fn batch_from_obj(dict: &ConfigObj, key: &str) -> Result<Self, ConfigError> {
+        let path = dict.key_path(key);
+        dict.array(key)?
+            .iter()
+            .enumerate()
+            .map(|(i, v)| Self::set_from_obj(v, &elem_path(&path, i)))
+            .collect::<Result<_, _>>()
+            .map(ModularMessage::Batch)
+    }
+}
+
+/*
+ * check_names against the names the render loop last published, if it
+ * publishes any. The config can still change before the message arrives, in
+ * which case the render loop drops it.
+ */
+pub fn check_names(
+    names: &Option<watch::Receiver<VarNames>>,
+    msg: &ModularMessage,
+    path: &str,
+) -> Result<(), ConfigError> {
+    match names {
+        Some(names) => msg.check_names(&names.borrow(), path),
+        None => Ok(()),
+    }
+}
+
+/*
+ * Requests for the render loop to report its current state. They are
+ * answered between frames, with None if there is nothing to report.
+ */
+#[derive(Debug)]
+pub enum ModularQuery {
+    /* The active config as it was received */
+    Config(oneshot::Sender<Option<JsonValue>>),
+    /* Every variable, in the form of the vars stanza */
+    Vars(oneshot::Sender<Option<JsonValue>>),
+    Var(VarType, VarId, oneshot::Sender<Option<JsonValue>>),
+}
+
+/* What the server needs from a render loop besides the message channel */
+pub struct RenderLink {
+    pub query: mpsc::Sender<ModularQuery>,
+    /* The variable names in the running config */
+    pub names: watch::Receiver<VarNames>,
 }
 
 /*
@@ -26,35 +187,41 @@ pub enum ModularMessage {
  * of Message is added.
  */
 pub trait Settable {
-    fn into_message(index: usize, value: Self) -> ModularMessage;
+    fn into_message(id: VarId, value: Self) -> ModularMessage;
 }
 
 impl Settable for f32 {
-    fn into_message(index: usize, value: Self) -> ModularMessage {
-        ModularMessage::SetScalar(VarMsg::<Self> {index, value})
+    fn into_message(id: VarId, value: Self) -> ModularMessage {
+        ModularMessage::SetScalar(VarMsg::<Self> {id, value})
     }
 }
 
 impl Settable for var_types::Position {
-    fn into_message(index: usize, value: Self) -> ModularMessage {
-        ModularMessage::SetPosition(VarMsg::<Self> {index, value})
+    fn into_message(id: VarId, value: Self) -> ModularMessage {
+        ModularMessage::SetPosition(VarMsg::<Self> {id, value})
     }
 }
 
 impl Settable for var_types::Color {
-    fn into_message(index: usize, value: Self) -> ModularMessage {
-        ModularMessage::SetColor(VarMsg::<Self> {index, value})
+    fn into_message(id: VarId, value: Self) -> ModularMessage {
+        ModularMessage::SetColor(VarMsg::<Self> {id, value})
     }
 }
 
 impl Settable for var_types::RealColor {
-    fn into_message(index: usize, value: Self) -> ModularMessage {
-        ModularMessage::SetRColor(VarMsg::<Self> {index, value})
+    fn into_message(id: VarId, value: Self) -> ModularMessage {
+        ModularMessage::SetRColor(VarMsg::<Self> {id, value})
     }
 }
 
 impl Settable for var_types::Data {
-    fn into_message(index: usize, value: Self) -> ModularMessage {
-        ModularMessage::SetData(VarMsg::<Self> {index, value})
+    fn into_message(id: VarId, value: Self) -> ModularMessage {
+        ModularMessage::SetData(VarMsg::<Self> {id, value})
+    }
+}
+
+impl Settable for Gradient {
+    fn into_message(id: VarId, value: Self) -> ModularMessage {
+        ModularMessage::SetGradient(VarMsg::<Self> {id, value})
     }
 }
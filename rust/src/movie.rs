@@ -2,20 +2,41 @@
 
 use tokio::sync;
 
+use clap::Parser;
+
+use args::MovieArgs;
+use dmx::dmx_pixels_main;
 use led_ctrl::led_main;
 use movie_ctrl::movie_main;
+use player::player_main;
+use player_msg::{PlayerMessage, RawFormat};
+use preview::with_preview;
 use server::server_run;
+use stream::{ddp_main, opc_main, FrameSink};
 
+mod args;
+mod config;
 mod constants;
+mod control_ws;
 mod display;
+mod dmx;
+mod gradient;
 mod led_ctrl;
 mod led_msg;
 mod modular_msg;
-mod server;
 mod movie_ctrl;
+mod player;
+mod player_msg;
+mod playlist_msg;
+mod preview;
+mod server;
+mod sim_display;
+mod stream;
 mod var_types;
 
 fn main() {
+    let args = MovieArgs::parse();
+
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .worker_threads(4)
@@ -26,7 +47,43 @@ fn main() {
     let (mod_cmd, mod_rx) = sync::broadcast::channel(16);
     let (led_cmd, led_rx) = sync::broadcast::channel(16);
 
-    rt.spawn(server_run(mod_cmd, led_cmd));
-    rt.spawn(led_main(led_rx));
-    rt.block_on(movie_main(mod_rx));
+    let (disp, preview_cmd) = with_preview(args.display.open(args.sim_fps), args.preview);
+
+    let (sink, frame_rx) = FrameSink::new();
+    if let Some(port) = args.opc {
+        rt.spawn(opc_main(port, sink.clone()));
+    }
+    if let Some(port) = args.ddp {
+        rt.spawn(ddp_main(port, sink.clone()));
+    }
+
+    if let Some(proto) = args.dmx {
+        rt.spawn(dmx_pixels_main(proto, args.dmx_universe, mod_cmd.clone()));
+    }
+
+    let (player_cmd, player_rx) = sync::mpsc::channel(16);
+    let media = std::fs::canonicalize(&args.media_dir)
+        .unwrap_or_else(|why| panic!("Couldn't open media directory {}: {why}", args.media_dir));
+    rt.spawn(player_main(sink.clone(), media, player_rx));
+    if let Some(path) = args.play {
+        let raw = args.raw.map(|(width, height)| RawFormat {
+            width,
+            height,
+            fps: args.raw_fps,
+        });
+        // A bad file is reported by the player and leaves it stopped
+        let (reply, _) = sync::oneshot::channel();
+        let msgs = [
+            PlayerMessage::Loop(args.looping),
+            PlayerMessage::Rate(args.rate),
+            PlayerMessage::Open { path, raw, reply },
+        ];
+        for msg in msgs {
+            player_cmd.try_send(msg).unwrap();
+        }
+    }
+
+    rt.spawn(server_run(mod_cmd, led_cmd, preview_cmd, None, None, None, Some(player_cmd)));
+    rt.spawn(led_main(args.display.open_white_led(), led_rx));
+    rt.block_on(movie_main(disp.as_ref(), mod_rx, frame_rx, sink));
 }
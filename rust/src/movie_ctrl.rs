@@ -1,48 +1,90 @@
-use std::thread::sleep;
+use std::sync::atomic::Ordering;
 use std::time::{Duration, Instant};
 
-use tokio::sync;
+use tokio::sync::{self, broadcast::error::RecvError};
 
 use crate::constants;
-use crate::display::LedDisplay;
+use crate::display::{blank_display, start_display, DisplayBackend};
 use crate::modular_msg::ModularMessage;
+use crate::stream::{Frame, FrameSink};
 
-pub async fn movie_main(mut rx_cfg: sync::broadcast::Receiver<ModularMessage>) {
-    /* Framebuffer initialization */
+/* How often to report on the stream inputs while they are active */
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
 
-    let disp = LedDisplay::new();
-    let id = disp.read_id();
+/* Copy an RGB frame in /set_data order to the framebuffer and flush it */
+fn show(disp: &dyn DisplayBackend, rgb: &[u8]) {
+    // Swizzle image
+    let mut fb = disp.borrow_fb();
+    for x in 0..constants::LED_COUNT {
+        for y in 0..constants::STRING_COUNT {
+            let dst_idx = constants::fb_idx(x, y);
+            let src_idx = constants::px_idx_tpose(x, y);
 
-    println!("FPGA ID: 0x{:x}", id);
-    println!("Starting empty count: {}", disp.empty_count());
+            // RGB to BRG
+            fb[dst_idx] = rgb[src_idx + 2];
+            fb[dst_idx + 1] = rgb[src_idx];
+            fb[dst_idx + 2] = rgb[src_idx + 1];
+        }
+    }
+    drop(fb);
 
-    let mut fb = disp.borrow_fb();
-    fb.fill(0);
+    disp.flush();
+}
+
+/*
+ * Shows frames from /set_data and from the stream inputs, whichever arrives.
+ * Stream frames that were superseded before they could be shown are counted
+ * as dropped.
+ */
+pub async fn movie_main(
+    disp: &dyn DisplayBackend,
+    mut rx_cfg: sync::broadcast::Receiver<ModularMessage>,
+    mut rx_frames: sync::watch::Receiver<Frame>,
+    sink: FrameSink,
+) {
+    start_display(disp);
 
     let now = Instant::now();
     let mut frame: u32 = 0;
 
-    // Blocking wait to receive new message
-    while let Ok(msg) = rx_cfg.recv().await {
-        match msg {
-            ModularMessage::SetData(buf) => {
-                // Swizzle image
-                for x in 0..constants::LED_COUNT {
-                    for y in 0..constants::STRING_COUNT {
-                        let dst_idx = constants::fb_idx(x, y);
-                        let src_idx = constants::px_idx_tpose(x, y);
+    let mut last_seq = 0;
+    let mut last_report = Instant::now();
 
-                        // RGB to BRG
-                        fb[dst_idx + 0] = buf.value[src_idx + 2];
-                        fb[dst_idx + 1] = buf.value[src_idx + 0];
-                        fb[dst_idx + 2] = buf.value[src_idx + 1];
-                    }
+    loop {
+        tokio::select! {
+            msg = rx_cfg.recv() => match msg {
+                Ok(ModularMessage::SetData(buf)) => {
+                    show(disp, &buf.value);
+                    frame += 1;
                 }
+                Ok(msg) => println!("Unimplemented: {:?}", msg),
+                Err(RecvError::Lagged(n)) => println!("Missed {n} messages"),
+                Err(RecvError::Closed) => break,
+            },
+            Ok(()) = rx_frames.changed() => {
+                // Don't hold the channel while flushing
+                let (seq, data) = {
+                    let f = rx_frames.borrow_and_update();
+                    (f.seq, f.data.clone())
+                };
+                sink.stats
+                    .dropped_frames
+                    .fetch_add(seq - last_seq - 1, Ordering::Relaxed);
+                last_seq = seq;
 
-                disp.flush();
+                show(disp, &data);
                 frame += 1;
+
+                if last_report.elapsed() >= STATS_INTERVAL {
+                    last_report = Instant::now();
+                    println!(
+                        "Stream inputs: {} frames received, {} dropped, {} packets lost",
+                        seq,
+                        sink.stats.dropped_frames.load(Ordering::Relaxed),
+                        sink.stats.lost_packets.load(Ordering::Relaxed)
+                    );
+                }
             },
-            _ => print!("Unimplemented: {:?}\n", msg),
         }
     }
 
@@ -50,21 +92,14 @@ pub async fn movie_main(mut rx_cfg: sync::broadcast::Receiver<ModularMessage>) {
         "{} frames in {:?}. Spent {:?} in flush.",
         frame,
         now.elapsed(),
-        disp.wait_time.get()
+        disp.wait_time()
+    );
+    println!(
+        "Stream inputs: {} frames received, {} dropped, {} packets lost",
+        last_seq,
+        sink.stats.dropped_frames.load(Ordering::Relaxed),
+        sink.stats.lost_packets.load(Ordering::Relaxed)
     );
 
-    // Wait for last frame to flush
-    sleep(Duration::from_millis(5));
-    disp.read_id();
-
-    // Blank
-    fb.fill(0);
-    disp.flush();
-    // Wait for DMA to finish. Otherwise the last blank frame doesn't get flushed.
-    sleep(Duration::from_millis(5));
-
-    while disp.empty_count() < 8000 {
-        sleep(Duration::from_micros(100));
-    }
-    println!("Ending empty count: {}", disp.empty_count());
+    blank_display(disp);
 }
@@ -0,0 +1,184 @@
+use crate::blocks::dither::{DitherMode, Residual};
+use crate::config::{ConfigError, ConfigObj};
+use crate::constants;
+use crate::render_block::{RenderState, COLOR_OUTPUT, RCOLOR_OUTPUT};
+use crate::var_types::{Color, FromJson, RealColor, VarType};
+
+/* Ordered dither offsets, the bit reversal of the phase (see blocks::dither) */
+const DITHER_ADD: [f32; 8] = [0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875];
+
+/* A fixed level, or one read from a float each pixel so it can be set live */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Level {
+    Fixed(f32),
+    Var(usize),
+}
+
+impl Level {
+    fn get(self, state: &RenderState) -> f32 {
+        match self {
+            Level::Fixed(v) => v,
+            Level::Var(idx) => state.get_scalar(idx),
+        }
+    }
+}
+
+/*
+ * Turns what a config renders into framebuffer values. Configs with an
+ * "output" member at the top level render linear light into rcolor 0, e.g.
+ *   "output": {"gamma": 1.0, "gain": {"r": 1.0, "g": 0.88, "b": 0.47},
+ *              "brightness": "level", "dither": true}
+ * Each channel is driven at brightness * gain * value^gamma, where the value
+ * is clamped to 0-1. Gamma stays at 1 for linear light, while blocks such as
+ * scalar_hsv2rgb that work in perceptual values want about 2.4. Brightness is
+ * a number or the name of a float. "dither" is true for ordered dithering or
+ * the name of a DitherMode, e.g. "temporal". Unlike the dither block's, its
+ * error diffusion modes don't keep the config from using render threads. All
+ * the members are optional and "source": "color" reads color 0 instead.
+ *
+ * Configs without an output member keep the old behavior of sending color 0
+ * to the framebuffer unchanged.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputStage {
+    source: VarType,
+    gamma: f32,
+    gain: RealColor,
+    brightness: Level,
+    dither: Option<DitherMode>,
+}
+
+impl Default for OutputStage {
+    fn default() -> Self {
+        OutputStage {
+            source: VarType::Color,
+            gamma: 1.0,
+            gain: RealColor {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+            },
+            brightness: Level::Fixed(1.0),
+            dither: None,
+        }
+    }
+}
+
+impl OutputStage {
+    pub fn from_config(dict: &ConfigObj, state: &RenderState) -> Result<Self, ConfigError> {
+        let mut stage = OutputStage::default();
+        if dict.opt("output").is_none() {
+            return Ok(stage);
+        }
+
+        let obj = dict.obj("output")?.with_names(state.names());
+        stage.source = VarType::RColor;
+
+        if obj.opt("source").is_some() {
+            stage.source = match obj.str("source")? {
+                "rcolor" => VarType::RColor,
+                "color" => VarType::Color,
+                _ => return Err(obj.err("source", "expected 'rcolor' or 'color'")),
+            };
+        }
+        if obj.opt("gamma").is_some() {
+            stage.gamma = obj.f32("gamma")?;
+            if !(stage.gamma > 0.0 && stage.gamma.is_finite()) {
+                return Err(obj.err("gamma", "expected a positive number"));
+            }
+        }
+        if obj.opt("gain").is_some() {
+            stage.gain = RealColor::from_obj(obj.value("gain")?, &obj.key_path("gain"))?;
+        }
+        if let Some(v) = obj.opt("brightness") {
+            stage.brightness = match v.as_f32() {
+                Some(level) => Level::Fixed(level),
+                None => Level::Var(obj.index("brightness", VarType::Scalar)?),
+            };
+        }
+        if let Some(v) = obj.opt("dither") {
+            let expected = "expected true, false, 'ordered', 'temporal' or 'floyd_steinberg'";
+            stage.dither = match (v.as_bool(), v.as_str()) {
+                (Some(true), _) => Some(DitherMode::Ordered),
+                (Some(false), _) => None,
+                (None, Some(name)) => {
+                    Some(DitherMode::from_name(name).ok_or_else(|| obj.err("dither", expected))?)
+                }
+                (None, None) => return Err(obj.err("dither", expected)),
+            };
+        }
+
+        Ok(stage)
+    }
+
+    /* The pool that holds the output color, which must have a slot 0 */
+    pub fn source(&self) -> VarType {
+        self.source
+    }
+
+    /* The brightness float, if any, for validation */
+    pub fn brightness_idx(&self) -> Option<usize> {
+        match self.brightness {
+            Level::Var(idx) => Some(idx),
+            Level::Fixed(_) => None,
+        }
+    }
+
+    /* How hard to drive each channel for the current pixel, 0 to 1 */
+    pub fn drive(&self, state: &RenderState) -> RealColor {
+        let (r, g, b) = match self.source {
+            VarType::RColor => {
+                let c = state.get_rcolor(RCOLOR_OUTPUT);
+                (c.r, c.g, c.b)
+            }
+            _ => {
+                let c = state.get_color(COLOR_OUTPUT);
+                let f = |x: u8| x as f32 / 255.0;
+                (f(c.r), f(c.g), f(c.b))
+            }
+        };
+
+        let level = self.brightness.get(state);
+        let f = |x: f32, gain: f32| level * gain * x.clamp(0.0, 1.0).powf(self.gamma);
+        RealColor {
+            r: f(r, self.gain.r),
+            g: f(g, self.gain.g),
+            b: f(b, self.gain.b),
+        }
+    }
+
+    /*
+     * Converts a frame of drive levels, in px_idx order, to 8-bit. Without
+     * dithering the levels are rounded, so the old behavior comes through
+     * unchanged. The error diffusion modes carry `residual` between frames.
+     */
+    pub fn quantize(
+        &self,
+        drive: &[RealColor],
+        frame: u32,
+        residual: &mut Residual,
+        out: &mut [Color],
+    ) {
+        for (i, (d, px)) in drive.iter().zip(out.iter_mut()).enumerate() {
+            let (x, y) = (i / constants::STRING_COUNT, i % constants::STRING_COUNT);
+            let offset = match self.dither {
+                None => 0.5,
+                Some(DitherMode::Ordered) => {
+                    let phase = x + 5 * y + 3 * (frame as usize % DITHER_ADD.len());
+                    DITHER_ADD[phase % DITHER_ADD.len()]
+                }
+                Some(mode) => {
+                    *px = residual.quantize(x, y, *d, mode == DitherMode::FloydSteinberg);
+                    continue;
+                }
+            };
+
+            let q = |v: f32| (255.0 * v + offset).floor().clamp(0.0, 255.0) as u8;
+            *px = Color {
+                r: q(d.r),
+                g: q(d.g),
+                b: q(d.b),
+            };
+        }
+    }
+}
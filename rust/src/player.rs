@@ -0,0 +1,487 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::BufReader;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, ImageDecoder, ImageReader, RgbaImage};
+use json::JsonValue;
+use tokio::sync::mpsc;
+
+use crate::constants;
+use crate::player_msg::{limits, PlayerMessage, RawFormat, MAX_RATE, MAX_SIDE, MIN_RATE};
+use crate::stream::FrameSink;
+
+/* GIFs with shorter delays are conventionally shown at 10 fps */
+const MIN_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/* GIF and APNG frames are kept once scaled, which is about 50 MB of these */
+const MAX_FRAMES: usize = 3000;
+/* The longest the player sleeps before checking the position again */
+const MAX_WAIT: Duration = Duration::from_secs(3600);
+
+/*
+ * Scales a w x h image to fit the grid, keeping its aspect ratio, and returns
+ * it in /set_data order. LEDs along a string are X_SCALE times closer than
+ * the strings are, so the grid is LED_COUNT * X_SCALE wide in string pitches.
+ * Each LED is the average of the source pixels in its cell, or the nearest
+ * one when scaling up. Anything outside the image is black.
+ */
+fn resample(w: usize, h: usize, pixel: impl Fn(usize, usize) -> [u8; 3]) -> Vec<u8> {
+    let grid_w = constants::LED_COUNT as f32 * constants::X_SCALE;
+    let grid_h = constants::STRING_COUNT as f32;
+
+    // Grid units per source pixel, and where the image sits in the grid
+    let k = (grid_w / w as f32).min(grid_h / h as f32);
+    let ox = (grid_w - w as f32 * k) / 2.0;
+    let oy = (grid_h - h as f32 * k) / 2.0;
+
+    // The source pixels whose centers are in [a, b), clipped to the image
+    let span = |a: f32, b: f32, len: usize| {
+        let lo = (a - 0.5).ceil().max(0.0) as usize;
+        let hi = ((b - 0.5).ceil().max(0.0) as usize).min(len);
+        lo..hi
+    };
+
+    let mut out = vec![0u8; constants::FRAME_SIZE_BYTES];
+    for y in 0..constants::STRING_COUNT {
+        let sy0 = (y as f32 - oy) / k;
+        let sy1 = (y as f32 + 1.0 - oy) / k;
+
+        for x in 0..constants::LED_COUNT {
+            let sx0 = (x as f32 * constants::X_SCALE - ox) / k;
+            let sx1 = ((x + 1) as f32 * constants::X_SCALE - ox) / k;
+
+            let mut sum = [0u32; 3];
+            let mut n = 0;
+            for sy in span(sy0, sy1, h) {
+                for sx in span(sx0, sx1, w) {
+                    let p = pixel(sx, sy);
+                    (0..3).for_each(|c| sum[c] += p[c] as u32);
+                    n += 1;
+                }
+            }
+
+            let rgb = if n > 0 {
+                sum.map(|s| (s / n) as u8)
+            } else {
+                let cx = (sx0 + sx1) / 2.0;
+                let cy = (sy0 + sy1) / 2.0;
+                if cx >= 0.0 && cy >= 0.0 && (cx as usize) < w && (cy as usize) < h {
+                    pixel(cx as usize, cy as usize)
+                } else {
+                    [0; 3]
+                }
+            };
+
+            let idx = constants::px_idx_tpose(x, y);
+            out[idx..idx + 3].copy_from_slice(&rgb);
+        }
+    }
+
+    out
+}
+
+/* Transparent pixels are shown over black */
+fn resample_rgba(img: &RgbaImage) -> Vec<u8> {
+    resample(img.width() as usize, img.height() as usize, |x, y| {
+        let [r, g, b, a] = img.get_pixel(x as u32, y as u32).0;
+        [r, g, b].map(|c| (c as u32 * a as u32 / 255) as u8)
+    })
+}
+
+/* Where a clip's frames come from */
+enum Frames {
+    /* Decoded and scaled up front, along with when each frame starts in seconds */
+    Decoded {
+        frames: Vec<Vec<u8>>,
+        starts: Vec<f64>,
+    },
+    /*
+     * Read from the file and scaled as each one is shown, so a raw file can be
+     * any length. Both happen on a blocking thread, which shares the file.
+     */
+    Raw {
+        file: Arc<File>,
+        format: RawFormat,
+        count: usize,
+    },
+}
+
+/* A file scaled to the grid */
+struct Clip {
+    frames: Frames,
+    length: f64,
+}
+
+impl Clip {
+    fn new(frames: Vec<(Vec<u8>, Duration)>) -> Result<Self, String> {
+        if frames.is_empty() {
+            return Err("no frames".to_string());
+        }
+
+        let mut starts = Vec::with_capacity(frames.len());
+        let mut length = 0.0;
+        let mut data = Vec::with_capacity(frames.len());
+        for (frame, delay) in frames {
+            starts.push(length);
+            length += delay.as_secs_f64();
+            data.push(frame);
+        }
+
+        Ok(Clip {
+            frames: Frames::Decoded {
+                frames: data,
+                starts,
+            },
+            length,
+        })
+    }
+
+    fn len(&self) -> usize {
+        match &self.frames {
+            Frames::Decoded { frames, .. } => frames.len(),
+            Frames::Raw { count, .. } => *count,
+        }
+    }
+
+    /* The frame showing at `t` seconds */
+    fn frame_at(&self, t: f64) -> usize {
+        match &self.frames {
+            Frames::Decoded { starts, .. } => starts.partition_point(|s| *s <= t).saturating_sub(1),
+            Frames::Raw { format, count, .. } => ((t * format.fps as f64) as usize).min(count - 1),
+        }
+    }
+
+    /* When frame `idx` gives way to the next, in seconds */
+    fn end_of(&self, idx: usize) -> f64 {
+        match &self.frames {
+            Frames::Decoded { starts, .. } => starts.get(idx + 1).copied().unwrap_or(self.length),
+            Frames::Raw { format, .. } => ((idx + 1) as f64 / format.fps as f64).min(self.length),
+        }
+    }
+
+    /* Frame `idx` in /set_data order */
+    async fn frame(&self, idx: usize) -> Result<Cow<'_, [u8]>, String> {
+        match &self.frames {
+            Frames::Decoded { frames, .. } => Ok(Cow::Borrowed(&frames[idx])),
+            Frames::Raw { file, format, .. } => {
+                let file = file.clone();
+                let format = *format;
+                tokio::task::spawn_blocking(move || read_raw(&file, format, idx))
+                    .await
+                    .map_err(|why| why.to_string())?
+                    .map(Cow::Owned)
+            }
+        }
+    }
+}
+
+/* Reads frame `idx` of a raw file and scales it to the grid */
+fn read_raw(file: &File, format: RawFormat, idx: usize) -> Result<Vec<u8>, String> {
+    let size = format.width * format.height * 3;
+    let mut buf = vec![0u8; size];
+    file.read_exact_at(&mut buf, idx as u64 * size as u64)
+        .map_err(|why| why.to_string())?;
+    Ok(resample(format.width, format.height, |x, y| {
+        let i = (y * format.width + x) * 3;
+        [buf[i], buf[i + 1], buf[i + 2]]
+    }))
+}
+
+/*
+ * The file `path` names within the media directory `media`, which must be
+ * canonical. Paths that lead outside it, including through symlinks, are
+ * refused.
+ */
+fn resolve(media: &Path, path: &str) -> Result<PathBuf, String> {
+    let full = media
+        .join(path)
+        .canonicalize()
+        .map_err(|why| why.to_string())?;
+    if !full.starts_with(media) {
+        return Err("outside the media directory".to_string());
+    }
+    Ok(full)
+}
+
+fn load_raw(path: &Path, raw: RawFormat) -> Result<Clip, String> {
+    if !(1..=MAX_SIDE).contains(&raw.width) || !(1..=MAX_SIDE).contains(&raw.height) {
+        return Err(format!(
+            "raw frames must be from 1x1 to {MAX_SIDE}x{MAX_SIDE}"
+        ));
+    }
+    if !(raw.fps > 0.0 && raw.fps.is_finite()) {
+        return Err("raw frame rate must be positive".to_string());
+    }
+
+    let file = File::open(path).map_err(|why| why.to_string())?;
+    let size = raw.width * raw.height * 3;
+    let len = file.metadata().map_err(|why| why.to_string())?.len();
+    let count = usize::try_from(len / size as u64).map_err(|_| "too many frames".to_string())?;
+    if count == 0 {
+        return Err("no frames".to_string());
+    }
+
+    Ok(Clip {
+        frames: Frames::Raw {
+            file: Arc::new(file),
+            format: raw,
+            count,
+        },
+        length: count as f64 / raw.fps as f64,
+    })
+}
+
+/* A still image is a clip of one frame that never advances */
+fn load_still(path: &Path) -> Result<Clip, String> {
+    let err = |why: image::ImageError| why.to_string();
+    let mut reader = ImageReader::open(path).map_err(|why| why.to_string())?;
+    reader.limits(limits());
+    let img = reader.decode().map_err(err)?;
+    Clip::new(vec![(resample_rgba(&img.to_rgba8()), Duration::ZERO)])
+}
+
+/*
+ * GIF, APNG or a still image in any format the image crate was built with.
+ * Animations are decoded up front, up to MAX_FRAMES.
+ */
+fn load_clip(path: &Path, raw: Option<RawFormat>) -> Result<Clip, String> {
+    if let Some(raw) = raw {
+        return load_raw(path, raw);
+    }
+
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let file = || {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|why| why.to_string())
+    };
+    let err = |why: image::ImageError| why.to_string();
+
+    let frames = match ext.as_deref() {
+        Some("gif") => {
+            let mut gif = GifDecoder::new(file()?).map_err(err)?;
+            gif.set_limits(limits()).map_err(err)?;
+            gif.into_frames()
+        }
+        Some("png") | Some("apng") => {
+            let png = PngDecoder::with_limits(file()?, limits()).map_err(err)?;
+            if png.is_apng().map_err(err)? {
+                png.apng().map_err(err)?.into_frames()
+            } else {
+                return load_still(path);
+            }
+        }
+        _ => return load_still(path),
+    };
+
+    let frames = frames
+        .take(MAX_FRAMES + 1)
+        .map(|f| {
+            let f = f.map_err(err)?;
+            let (num, den) = f.delay().numer_denom_ms();
+            let delay = match Duration::from_secs_f64(num as f64 / den.max(1) as f64 / 1000.0) {
+                d if d < MIN_DELAY => DEFAULT_DELAY,
+                d => d,
+            };
+            Ok((resample_rgba(f.buffer()), delay))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if frames.len() > MAX_FRAMES {
+        return Err(format!("more than {MAX_FRAMES} frames"));
+    }
+
+    Clip::new(frames)
+}
+
+/*
+ * Plays a clip into the stream FrameSink, so file playback and the stream
+ * inputs share movie_main's frame-drop accounting. The position advances at
+ * `rate` times real time from wherever it was at `since`.
+ */
+struct Player {
+    sink: FrameSink,
+    /* Files are opened relative to this, and may not leave it */
+    media: PathBuf,
+
+    path: Option<String>,
+    clip: Option<Clip>,
+    shown: Option<usize>,
+
+    playing: bool,
+    looping: bool,
+    rate: f32,
+    position: f64,
+    since: Instant,
+}
+
+impl Player {
+    fn new(sink: FrameSink, media: PathBuf) -> Self {
+        Player {
+            sink,
+            media,
+            path: None,
+            clip: None,
+            shown: None,
+            playing: false,
+            looping: true,
+            rate: 1.0,
+            position: 0.0,
+            since: Instant::now(),
+        }
+    }
+
+    /* Brings `position` up to now */
+    fn rebase(&mut self) {
+        let now = Instant::now();
+        if self.playing {
+            self.position += now.duration_since(self.since).as_secs_f64() * self.rate as f64;
+        }
+        self.since = now;
+    }
+
+    /* Shows the frame for the current position and returns when the next one is due */
+    async fn tick(&mut self) -> Option<Instant> {
+        self.rebase();
+        let clip = self.clip.as_ref()?;
+
+        if self.looping && clip.length > 0.0 {
+            self.position = self.position.rem_euclid(clip.length);
+        } else if self.position >= clip.length {
+            // Hold the last frame
+            self.position = clip.length;
+            self.playing = false;
+        }
+
+        let idx = clip.frame_at(self.position);
+        if self.shown != Some(idx) {
+            match clip.frame(idx).await {
+                Ok(frame) => self.sink.publish(&frame),
+                Err(why) => println!("Couldn't read frame {idx}: {why}"),
+            }
+            self.shown = Some(idx);
+        }
+
+        if !self.playing {
+            return None;
+        }
+
+        let wait = (clip.end_of(idx) - self.position) / self.rate as f64;
+        let wait = Duration::try_from_secs_f64(wait.max(0.0)).map_or(MAX_WAIT, |w| w.min(MAX_WAIT));
+        Some(self.since + wait)
+    }
+
+    async fn open(&mut self, path: String, raw: Option<RawFormat>) -> Result<(), String> {
+        let load_path = path.clone();
+        let media = self.media.clone();
+        let clip =
+            tokio::task::spawn_blocking(move || load_clip(&resolve(&media, &load_path)?, raw))
+                .await
+                .map_err(|why| why.to_string())??;
+
+        println!("Playing {path}: {} frames, {:.1}s", clip.len(), clip.length);
+        self.path = Some(path);
+        self.clip = Some(clip);
+        self.shown = None;
+        self.position = 0.0;
+        self.playing = true;
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.path = None;
+        self.clip = None;
+        self.shown = None;
+        self.playing = false;
+        self.position = 0.0;
+        self.sink.publish(&[0u8; constants::FRAME_SIZE_BYTES]);
+    }
+
+    async fn handle(&mut self, msg: PlayerMessage) {
+        self.rebase();
+        match msg {
+            PlayerMessage::Open { path, raw, reply } => {
+                let result = self.open(path.clone(), raw).await;
+                if let Err(why) = &result {
+                    println!("Couldn't play {path}: {why}");
+                }
+                // Nobody may be waiting, e.g. when started from the command line
+                let _ = reply.send(result);
+            }
+            PlayerMessage::Play => {
+                // Start over if it played to the end
+                if let Some(clip) = &self.clip {
+                    if !self.looping && self.position >= clip.length {
+                        self.position = 0.0;
+                    }
+                }
+                self.playing = self.clip.is_some();
+            }
+            PlayerMessage::Pause => self.playing = false,
+            PlayerMessage::Stop => self.stop(),
+            PlayerMessage::Seek(t) => self.position = t.max(0.0) as f64,
+            PlayerMessage::Rate(rate) => {
+                if rate > 0.0 && rate.is_finite() {
+                    self.rate = rate.clamp(MIN_RATE, MAX_RATE);
+                }
+            }
+            PlayerMessage::Loop(looping) => self.looping = looping,
+            PlayerMessage::Status(reply) => {
+                let _ = reply.send(self.status());
+            }
+        }
+    }
+
+    fn status(&self) -> JsonValue {
+        let (frames, length) = match &self.clip {
+            Some(clip) => (clip.len(), clip.length),
+            None => (0, 0.0),
+        };
+
+        json::object! {
+            file: self.path.clone(),
+            playing: self.playing,
+            loop: self.looping,
+            rate: self.rate,
+            // Milliseconds are plenty and keep f64 noise out of the output
+            position: (self.position * 1000.0).round() / 1000.0,
+            length: (length * 1000.0).round() / 1000.0,
+            frames: frames,
+        }
+    }
+}
+
+/*
+ * Runs the player until the command channel closes. Files are looked up in
+ * `media`, which must be canonical.
+ */
+pub async fn player_main(sink: FrameSink, media: PathBuf, mut rx: mpsc::Receiver<PlayerMessage>) {
+    let mut player = Player::new(sink, media);
+
+    loop {
+        let deadline = player.tick().await;
+        let next_frame = async {
+            match deadline {
+                Some(t) => tokio::time::sleep_until(t.into()).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = next_frame => {}
+            msg = rx.recv() => match msg {
+                Some(msg) => player.handle(msg).await,
+                None => break,
+            },
+        }
+    }
+}
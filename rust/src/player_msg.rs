@@ -0,0 +1,104 @@
+use image::Limits;
+use json::JsonValue;
+use tokio::sync::oneshot;
+
+use crate::config::{ConfigError, ConfigObj};
+use crate::var_types::FromJson;
+
+/* The largest image or raw frame that will be decoded, in either direction */
+pub const MAX_SIDE: usize = 4096;
+
+/* Keeps the decoders from allocating for images larger than MAX_SIDE */
+pub fn limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SIDE as u32);
+    limits.max_image_height = Some(MAX_SIDE as u32);
+    limits
+}
+
+/* Playback rates are clamped to this range */
+pub const MIN_RATE: f32 = 0.01;
+pub const MAX_RATE: f32 = 100.0;
+
+/* The frame layout of a raw RGB file, which has no header to say */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawFormat {
+    pub width: usize,
+    pub height: usize,
+    pub fps: f32,
+}
+
+impl FromJson for RawFormat {
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        let dict = ConfigObj::new(v, path)?;
+
+        let fps = dict.f32("fps")?;
+        if !(fps > 0.0 && fps.is_finite()) {
+            return Err(dict.err("fps", "expected a positive frame rate"));
+        }
+
+        let side = |key: &str| {
+            let n = dict.usize(key)?;
+            if !(1..=MAX_SIDE).contains(&n) {
+                return Err(dict.err(key, format!("expected a {key} in [1, {MAX_SIDE}]")));
+            }
+            Ok(n)
+        };
+
+        Ok(RawFormat {
+            width: side("width")?,
+            height: side("height")?,
+            fps,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum PlayerMessage {
+    /* Loads a file and plays it from the start, replying with any error */
+    Open {
+        path: String,
+        raw: Option<RawFormat>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    Play,
+    Pause,
+    /* Unloads the file and blanks the display */
+    Stop,
+    /* Position in seconds */
+    Seek(f32),
+    /* Clamped to [MIN_RATE, MAX_RATE] */
+    Rate(f32),
+    Loop(bool),
+    /* Replies with the file, position and settings */
+    Status(oneshot::Sender<JsonValue>),
+}
+
+impl PlayerMessage {
+    /*
+     * The optional playback settings in a request body, e.g.
+     *   {"position": 2.5, "rate": 0.5, "loop": false}
+     */
+    pub fn settings_from_obj(dict: &ConfigObj) -> Result<Vec<PlayerMessage>, ConfigError> {
+        let mut msgs = Vec::new();
+
+        if let Some(v) = dict.opt("loop") {
+            match v.as_bool() {
+                Some(looping) => msgs.push(PlayerMessage::Loop(looping)),
+                None => return Err(dict.err("loop", "expected true or false")),
+            }
+        }
+        if dict.opt("rate").is_some() {
+            let rate = dict.f32("rate")?;
+            if !(rate > 0.0 && rate.is_finite()) {
+                return Err(dict.err("rate", "expected a positive playback rate"));
+            }
+            msgs.push(PlayerMessage::Rate(rate));
+        }
+        if dict.opt("position").is_some() {
+            msgs.push(PlayerMessage::Seek(dict.f32("position")?));
+        }
+
+        Ok(msgs)
+    }
+}
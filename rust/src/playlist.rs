@@ -0,0 +1,443 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::Timelike;
+use json::JsonValue;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::config::{ConfigError, ConfigObj};
+use crate::mod_ctrl::validate_config;
+use crate::modular_msg::ModularMessage;
+use crate::playlist_msg::PlaylistMessage;
+use crate::transition::Transition;
+use crate::var_types::FromJson;
+
+/* How often the schedule file and the current entry are checked */
+const TICK: Duration = Duration::from_secs(1);
+
+/* A time of day range in seconds since local midnight. It wraps past midnight
+ * if it ends before it starts, and covers the whole day if they are equal. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Window {
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    fn contains(&self, t: u32) -> bool {
+        if self.start < self.end {
+            self.start <= t && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+}
+
+/* Parses "HH:MM" or "HH:MM:SS" */
+fn parse_time(dict: &ConfigObj, key: &str) -> Result<u32, ConfigError> {
+    let err = || dict.err(key, "expected a time of day as HH:MM or HH:MM:SS");
+
+    let fields = dict
+        .str(key)?
+        .split(':')
+        .map(|f| f.parse::<u32>().map_err(|_| err()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match fields[..] {
+        [h, m] if h < 24 && m < 60 => Ok(h * 3600 + m * 60),
+        [h, m, s] if h < 24 && m < 60 && s < 60 => Ok(h * 3600 + m * 60 + s),
+        _ => Err(err()),
+    }
+}
+
+fn fmt_time(t: u32) -> String {
+    format!("{:02}:{:02}:{:02}", t / 3600, t / 60 % 60, t % 60)
+}
+
+/* When an entry plays: in turn for a fixed time, or throughout a window */
+#[derive(Debug, Clone, PartialEq)]
+enum When {
+    Duration(Duration),
+    Window(Window),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    /* Config file, relative to the schedule */
+    config: String,
+    when: When,
+    /* Overrides any transition in the config itself */
+    transition: Option<JsonValue>,
+}
+
+/* Checks the "transition" member of `dict`, if any, and returns it */
+fn transition_of(dict: &ConfigObj) -> Result<Option<JsonValue>, ConfigError> {
+    Transition::from_config(dict)?;
+    Ok(dict.opt("transition").cloned())
+}
+
+impl FromJson for Entry {
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        let dict = ConfigObj::new(v, path)?;
+
+        let when = match (dict.opt("duration"), dict.opt("from")) {
+            (Some(_), None) => {
+                let secs = dict.f32("duration")?;
+                if !(secs > 0.0 && secs.is_finite()) {
+                    return Err(dict.err("duration", "expected a positive number of seconds"));
+                }
+                When::Duration(Duration::from_secs_f32(secs))
+            }
+            (None, Some(_)) => When::Window(Window {
+                start: parse_time(&dict, "from")?,
+                end: parse_time(&dict, "to")?,
+            }),
+            (Some(_), Some(_)) => {
+                return Err(dict.err(
+                    "duration",
+                    "expected either a duration or a from/to window, not both",
+                ))
+            }
+            (None, None) => return Err(dict.err("duration", "missing duration or from/to window")),
+        };
+
+        Ok(Entry {
+            config: dict.str("config")?.to_string(),
+            when,
+            transition: transition_of(&dict)?,
+        })
+    }
+}
+
+impl Entry {
+    fn to_json(&self) -> JsonValue {
+        let mut obj = json::object! {config: self.config.clone()};
+        match self.when {
+            When::Duration(d) => obj["duration"] = d.as_secs_f32().into(),
+            When::Window(w) => {
+                obj["from"] = fmt_time(w.start).into();
+                obj["to"] = fmt_time(w.end).into();
+            }
+        }
+        if let Some(t) = &self.transition {
+            obj["transition"] = t.clone();
+        }
+        obj
+    }
+}
+
+/*
+ * The contents of a schedule file, e.g.
+ *   {
+ *     "transition": {"duration": 3},
+ *     "entries": [
+ *       {"config": "rainbow_pulse.json", "duration": 300},
+ *       {"config": "chill.json", "from": "22:00", "to": "06:30"}
+ *     ]
+ *   }
+ * The optional top-level transition applies to configs that don't have one.
+ */
+#[derive(Debug, Default)]
+struct Schedule {
+    entries: Vec<Entry>,
+    transition: Option<JsonValue>,
+}
+
+impl FromJson for Schedule {
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        let dict = ConfigObj::new(v, path)?;
+
+        Ok(Schedule {
+            entries: dict.list("entries")?,
+            transition: transition_of(&dict)?,
+        })
+    }
+}
+
+struct Playing {
+    entry: Entry,
+    since: Instant,
+}
+
+/*
+ * Feeds configs to the render loop according to a schedule file. Entries with
+ * a window play whenever the local time is inside it, the first match taking
+ * priority. The rest take turns, each for its duration, in file order.
+ *
+ * The schedule file is reloaded when its modification time changes. A file
+ * that fails to parse is reported and the previous schedule kept.
+ */
+pub struct Playlist {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    schedule: Schedule,
+
+    mod_cmd: broadcast::Sender<ModularMessage>,
+
+    running: bool,
+    playing: Option<Playing>,
+    /* Index of the next entry with a duration */
+    next: usize,
+    /* A window entry that was skipped, ignored until its window closes */
+    skipped: Option<Entry>,
+}
+
+impl Playlist {
+    pub fn new(path: &str, mod_cmd: broadcast::Sender<ModularMessage>) -> Self {
+        let mut playlist = Playlist {
+            path: PathBuf::from(path),
+            modified: None,
+            schedule: Schedule::default(),
+            mod_cmd,
+            running: false,
+            playing: None,
+            next: 0,
+            skipped: None,
+        };
+        playlist.reload();
+        if playlist.modified.is_none() {
+            println!(
+                "Playlist {} not found, waiting for it",
+                playlist.path.display()
+            );
+        }
+
+        playlist
+    }
+
+    fn reload(&mut self) {
+        let modified = match fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(t) => t,
+            Err(why) => {
+                if self.modified.take().is_some() {
+                    println!("Playlist {} is unavailable: {why}", self.path.display());
+                }
+                return;
+            }
+        };
+        if self.modified == Some(modified) {
+            return;
+        }
+        self.modified = Some(modified);
+
+        let schedule = fs::read_to_string(&self.path)
+            .map_err(|why| ConfigError::new("", why.to_string()))
+            .and_then(|s| json::parse(&s).map_err(|why| ConfigError::new("", why.to_string())))
+            .and_then(|v| Schedule::from_obj(&v, ""));
+
+        match schedule {
+            Ok(schedule) => {
+                println!("Playlist loaded: {} entries", schedule.entries.len());
+                self.schedule = schedule;
+                self.next = 0;
+            }
+            Err(why) => println!("Rejected playlist {}: {why}", self.path.display()),
+        }
+    }
+
+    /*
+     * The config file an entry names, relative to the schedule's directory.
+     * Paths that lead outside it, including through symlinks, are refused.
+     */
+    fn resolve(&self, config: &str) -> Result<PathBuf, ConfigError> {
+        let err = |why: std::io::Error| ConfigError::new("config", why.to_string());
+        let schedule = self.path.canonicalize().map_err(err)?;
+        let dir = schedule.parent().unwrap_or(&schedule);
+        let full = dir.join(config).canonicalize().map_err(err)?;
+        if !full.starts_with(dir) {
+            return Err(ConfigError::new(
+                "config",
+                "outside the schedule's directory",
+            ));
+        }
+        Ok(full)
+    }
+
+    /* Reads an entry's config and applies the transition it should use */
+    fn load_config(&self, entry: &Entry) -> Result<json::object::Object, ConfigError> {
+        let path = self.resolve(&entry.config)?;
+        let s = fs::read_to_string(&path).map_err(|why| ConfigError::new("", why.to_string()))?;
+
+        let mut cfg = match json::parse(&s) {
+            Ok(JsonValue::Object(cfg)) => cfg,
+            Ok(_) => return Err(ConfigError::new("", "JSON configuration is not an object")),
+            Err(why) => return Err(ConfigError::new("", why.to_string())),
+        };
+
+        let transition = entry.transition.as_ref().or(self
+            .schedule
+            .transition
+            .as_ref()
+            .filter(|_| cfg.get("transition").is_none()));
+        if let Some(t) = transition {
+            cfg.insert("transition", t.clone());
+        }
+
+        validate_config(&cfg)?;
+        Ok(cfg)
+    }
+
+    /*
+     * A config that fails to load still counts as playing, so that a bad
+     * entry is reported once rather than retried every tick. The ceiling
+     * keeps showing whatever was there before.
+     */
+    fn play(&mut self, entry: Entry) {
+        match self.load_config(&entry) {
+            Ok(cfg) => {
+                println!("Playlist: playing {}", entry.config);
+                if let Err(why) = self.mod_cmd.send(ModularMessage::Config(cfg)) {
+                    println!("Error sending new config: {why}");
+                }
+            }
+            Err(why) => println!("Playlist: skipping {}: {why}", entry.config),
+        }
+
+        self.playing = Some(Playing {
+            entry,
+            since: Instant::now(),
+        });
+    }
+
+    /* Plays the next entry with a duration, if there are any */
+    fn advance(&mut self) {
+        let entries = &self.schedule.entries;
+        let found = (0..entries.len())
+            .map(|i| (self.next + i) % entries.len())
+            .find(|i| matches!(entries[*i].when, When::Duration(_)));
+
+        match found {
+            Some(i) => {
+                self.next = i + 1;
+                self.play(entries[i].clone());
+            }
+            None => self.playing = None,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.reload();
+        if !self.running {
+            return;
+        }
+
+        let now = chrono::Local::now().num_seconds_from_midnight();
+        let in_window = |e: &Entry| matches!(e.when, When::Window(w) if w.contains(now));
+
+        if self.skipped.as_ref().is_some_and(|e| !in_window(e)) {
+            self.skipped = None;
+        }
+
+        let window = self
+            .schedule
+            .entries
+            .iter()
+            .find(|e| in_window(e) && self.skipped.as_ref() != Some(*e))
+            .cloned();
+
+        match window {
+            Some(entry) => {
+                if self.playing.as_ref().map(|p| &p.entry) != Some(&entry) {
+                    self.play(entry);
+                }
+            }
+            None => {
+                let due = match &self.playing {
+                    Some(Playing {
+                        entry:
+                            Entry {
+                                when: When::Duration(d),
+                                ..
+                            },
+                        since,
+                    }) => since.elapsed() >= *d,
+                    _ => true,
+                };
+                if due {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    pub fn start(&mut self) {
+        if !self.running {
+            self.running = true;
+            self.playing = None;
+            self.tick();
+        }
+    }
+
+    /* Leaves the current config running */
+    pub fn stop(&mut self) {
+        self.running = false;
+        self.playing = None;
+        self.skipped = None;
+    }
+
+    pub fn skip(&mut self) {
+        if !self.running {
+            return;
+        }
+
+        if let Some(Playing { entry, .. }) = &self.playing {
+            if matches!(entry.when, When::Window(_)) {
+                self.skipped = Some(entry.clone());
+            }
+        }
+        self.advance();
+    }
+
+    pub fn status(&self) -> JsonValue {
+        let playing = match &self.playing {
+            Some(p) => {
+                let elapsed = p.since.elapsed();
+                let remaining = match p.entry.when {
+                    When::Duration(d) => d.saturating_sub(elapsed).as_secs_f32().into(),
+                    When::Window(_) => JsonValue::Null,
+                };
+                json::object! {
+                    config: p.entry.config.clone(),
+                    elapsed: elapsed.as_secs_f32(),
+                    remaining: remaining,
+                }
+            }
+            None => JsonValue::Null,
+        };
+
+        json::object! {
+            file: self.path.display().to_string(),
+            running: self.running,
+            playing: playing,
+            entries: self.schedule.entries.iter().map(Entry::to_json).collect::<Vec<_>>(),
+        }
+    }
+}
+
+/* Runs the playlist until the command channel closes. It starts out playing. */
+pub async fn playlist_main(
+    path: String,
+    mod_cmd: broadcast::Sender<ModularMessage>,
+    mut rx: mpsc::Receiver<PlaylistMessage>,
+) {
+    let mut playlist = Playlist::new(&path, mod_cmd);
+    playlist.start();
+
+    let mut ticks = tokio::time::interval(TICK);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => playlist.tick(),
+            msg = rx.recv() => match msg {
+                Some(PlaylistMessage::Start) => playlist.start(),
+                Some(PlaylistMessage::Stop) => playlist.stop(),
+                Some(PlaylistMessage::Skip) => playlist.skip(),
+                Some(PlaylistMessage::Status(reply)) => {
+                    // The requester may have gone away
+                    let _ = reply.send(playlist.status());
+                }
+                None => break,
+            },
+        }
+    }
+}
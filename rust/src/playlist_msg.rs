@@ -0,0 +1,11 @@
+use json::JsonValue;
+use tokio::sync::oneshot;
+
+#[derive(Debug)]
+pub enum PlaylistMessage {
+    Start,
+    Stop,
+    Skip,
+    /* Replies with the schedule and what is currently playing */
+    Status(oneshot::Sender<JsonValue>),
+}
@@ -0,0 +1,138 @@
+use std::cell::RefMut;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use hyper_tungstenite::{tungstenite::Message, HyperWebsocket};
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+
+use crate::constants;
+use crate::display::{DisplayBackend, WhiteLed};
+
+/*
+ * One flushed frame in logical order: LED_COUNT * STRING_COUNT RGB triplets,
+ * x varying fastest. This is the same layout /set_data takes.
+ */
+pub type PreviewFrame = Arc<Vec<u8>>;
+
+/* The page that draws the preview stream */
+const PREVIEW_HTML: &str = include_str!("preview.html");
+
+/*
+ * Wraps another display and publishes a copy of every flushed frame. The copy
+ * is only made while at least one client is subscribed.
+ */
+pub struct PreviewDisplay {
+    inner: Box<dyn DisplayBackend>,
+    tx: Sender<PreviewFrame>,
+}
+
+impl PreviewDisplay {
+    pub fn new(inner: Box<dyn DisplayBackend>, tx: Sender<PreviewFrame>) -> Self {
+        PreviewDisplay { inner, tx }
+    }
+}
+
+/* Wraps the display in a PreviewDisplay if enabled, returning the frame channel */
+pub fn with_preview(
+    disp: Box<dyn DisplayBackend>,
+    enable: bool,
+) -> (Box<dyn DisplayBackend>, Option<Sender<PreviewFrame>>) {
+    if !enable {
+        return (disp, None);
+    }
+
+    // Frames are large and only the latest matters, so keep the queue short
+    let (tx, _) = broadcast::channel(4);
+    (Box::new(PreviewDisplay::new(disp, tx.clone())), Some(tx))
+}
+
+/* Undo the fb_idx layout and BRG ordering of the framebuffer */
+pub fn fb_to_rgb(fb: &[u8]) -> Vec<u8> {
+    let mut rgb = vec![0u8; constants::FRAME_SIZE_BYTES];
+
+    for x in 0..constants::LED_COUNT {
+        for y in 0..constants::STRING_COUNT {
+            let src_idx = constants::fb_idx(x, y);
+            let dst_idx = constants::px_idx_tpose(x, y);
+
+            // BRG to RGB
+            rgb[dst_idx] = fb[src_idx + 1];
+            rgb[dst_idx + 1] = fb[src_idx + 2];
+            rgb[dst_idx + 2] = fb[src_idx];
+        }
+    }
+
+    rgb
+}
+
+impl WhiteLed for PreviewDisplay {
+    fn set_white_led(&self, cold: u8, cool: u8, hot: u8) {
+        self.inner.set_white_led(cold, cool, hot)
+    }
+}
+
+impl DisplayBackend for PreviewDisplay {
+    fn read_id(&self) -> u16 {
+        self.inner.read_id()
+    }
+
+    fn empty_count(&self) -> usize {
+        self.inner.empty_count()
+    }
+
+    fn borrow_fb(&self) -> RefMut<'_, [u8]> {
+        self.inner.borrow_fb()
+    }
+
+    fn flush(&self) {
+        if self.tx.receiver_count() > 0 {
+            let frame = fb_to_rgb(&self.inner.borrow_fb());
+            // Nobody listening is not an error
+            let _ = self.tx.send(Arc::new(frame));
+        }
+        self.inner.flush();
+    }
+
+    fn wait_time(&self) -> Duration {
+        self.inner.wait_time()
+    }
+}
+
+/* Fill in the grid geometry so the page always matches the build */
+pub fn preview_page() -> String {
+    PREVIEW_HTML
+        .replace("{{LED_COUNT}}", &constants::LED_COUNT.to_string())
+        .replace("{{STRING_COUNT}}", &constants::STRING_COUNT.to_string())
+        .replace("{{X_SCALE}}", &constants::X_SCALE.to_string())
+}
+
+/* Forward frames to a single WebSocket client until either side goes away */
+pub async fn preview_ws(ws: HyperWebsocket, mut rx: Receiver<PreviewFrame>) {
+    let mut ws = match ws.await {
+        Ok(ws) => ws,
+        Err(why) => {
+            println!("Preview WebSocket handshake failed: {why}");
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            frame = rx.recv() => match frame {
+                Ok(frame) => {
+                    if ws.send(Message::binary(frame.to_vec())).await.is_err() {
+                        break;
+                    }
+                }
+                // A slow client just misses frames
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            msg = ws.next() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => continue,
+            },
+        }
+    }
+}
@@ -1,16 +1,168 @@
+use crate::config::{elem_path, key_path, ConfigError, ConfigObj};
+use crate::constants;
+use crate::gradient::Gradient;
 use crate::var_types::*;
 use json::JsonValue;
+use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct RenderState {
     scalars: Vec<f32>,
     positions: Vec<Position>,
     colors: Vec<Color>,
     rcolors: Vec<RealColor>,
-    data: Vec<Data>,
+    // Shared so that copying the state for each render thread is cheap
+    data: Vec<Arc<Data>>,
+    gradients: Vec<Arc<Gradient>>,
+    // SCALAR_FRAME as an integer, which stays exact past 2^24 frames
+    frame: u32,
+
+    names: VarNames,
+}
+
+/* Scalars written by the render loop before any block runs */
+pub const SCALAR_FRAME: usize = 0;
+pub const SCALAR_X: usize = 1;
+pub const SCALAR_Y: usize = 2;
+pub const RESERVED_SCALARS: usize = 3;
+
+/*
+ * Wall-clock inputs, also written by the render loop before each frame. These
+ * are bound by name rather than reserving more indices, so a config opts in by
+ * declaring a float with one of these names. Times are in seconds.
+ */
+pub const NAME_ELAPSED: &str = "elapsed";
+pub const NAME_DT: &str = "dt";
+pub const NAME_TIME_OF_DAY: &str = "time_of_day";
+pub const CLOCK_NAMES: [&str; 3] = [NAME_ELAPSED, NAME_DT, NAME_TIME_OF_DAY];
+/*
+ * Elapsed time wraps back to 0 after this many seconds, so that it keeps
+ * millisecond resolution as an f32. Anything periodic whose period divides an
+ * hour, e.g. a ramp at 0.25 Hz, carries on seamlessly across the wrap.
+ */
+pub const ELAPSED_PERIOD: f64 = 3600.0;
+
+/* The color written to the framebuffer for each pixel */
+pub const COLOR_OUTPUT: usize = 0;
+/* The same, for configs with an output stage (see OutputStage) */
+pub const RCOLOR_OUTPUT: usize = 0;
+
+/* A connection between a block and a slot in RenderState */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Port {
+    /* The key in the block's inputs or outputs, e.g. `m[2]` */
+    pub name: String,
+    pub ty: VarType,
+    pub index: usize,
+}
+
+impl Port {
+    pub fn new(name: impl Into<String>, ty: VarType, index: usize) -> Self {
+        Port {
+            name: name.into(),
+            ty,
+            index,
+        }
+    }
+
+    pub fn scalar(name: impl Into<String>, index: usize) -> Self {
+        Self::new(name, VarType::Scalar, index)
+    }
+
+    /* One port per element of an array input such as `m: [4, 5, 6]` */
+    pub fn scalars(name: &str, idxs: &[usize]) -> Vec<Self> {
+        idxs.iter()
+            .enumerate()
+            .map(|(i, idx)| Self::scalar(format!("{name}[{i}]"), *idx))
+            .collect()
+    }
+}
+
+/* Whether a block can be scheduled by its inputs alone */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purity {
+    /* The outputs depend only on the inputs, so it may run once per frame
+     * when none of its inputs vary per pixel */
+    Pure,
+    /* Keeps state, but only advances once per frame however many times it
+     * runs, so it may be hoisted like a pure block */
+    PerFrame,
+    /* Keeps state between calls and must run for every pixel */
+    Stateful,
+}
+
+/* The pixel nearest a position, if it is on the grid */
+pub fn pixel_at(x: f32, y: f32) -> Option<(usize, usize)> {
+    let (x, y) = (x.round(), y.round());
+    // Also catches NaN
+    if !(x >= 0.0 && y >= 0.0) {
+        return None;
+    }
+    let (x, y) = (x as usize, y as usize);
+    (x < constants::LED_COUNT && y < constants::STRING_COUNT).then_some((x, y))
+}
+
+/*
+ * A value per pixel that a block keeps from one frame to the next, looked up by
+ * x and y (see pixel_at). Pixels off the grid have no slot.
+ *
+ * A block holding one has to be Stateful, which makes graph::compile mark the
+ * graph serial. Such graphs get no render threads, so the one copy of the
+ * block sees every pixel in order.
+ */
+#[derive(Debug, Clone)]
+pub struct PixelBuffer<T> {
+    values: Vec<T>,
+}
+
+impl<T: Clone + Default> PixelBuffer<T> {
+    pub fn new() -> Self {
+        PixelBuffer {
+            values: vec![T::default(); constants::PIXEL_COUNT],
+        }
+    }
+
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
+        if x >= constants::LED_COUNT || y >= constants::STRING_COUNT {
+            return None;
+        }
+        self.values.get_mut(y + x * constants::STRING_COUNT)
+    }
 }
 
-pub trait RenderBlock {
+/*
+ * Blocks are cloned so that each render thread can run its own copy. Deriving
+ * Clone on the block is enough to get this.
+ */
+pub trait CloneBlock {
+    fn clone_block(&self) -> Box<dyn RenderBlock>;
+}
+
+impl<T: RenderBlock + Clone + 'static> CloneBlock for T {
+    fn clone_block(&self) -> Box<dyn RenderBlock> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn RenderBlock> {
+    fn clone(&self) -> Self {
+        self.clone_block()
+    }
+}
+
+pub trait RenderBlock: CloneBlock + Send {
     fn execute(&mut self, state: &mut RenderState);
+
+    /* Every slot read by execute() */
+    fn inputs(&self) -> Vec<Port>;
+
+    /* Every slot written by execute() */
+    fn outputs(&self) -> Vec<Port>;
+
+    /* Blocks must opt in to being hoisted out of the pixel loop */
+    fn purity(&self) -> Purity {
+        Purity::Stateful
+    }
 }
 
 impl RenderState {
@@ -21,7 +173,8 @@ impl RenderState {
         // At least 1 color for output
         let colors = Vec::<Color>::with_capacity(1);
         let rcolors = Vec::<RealColor>::with_capacity(0);
-        let data = Vec::<Vec<u8>>::with_capacity(0);
+        let data = Vec::<Arc<Data>>::with_capacity(0);
+        let gradients = Vec::<Arc<Gradient>>::with_capacity(0);
 
         RenderState {
             scalars,
@@ -29,21 +182,57 @@ impl RenderState {
             colors,
             rcolors,
             data,
+            gradients,
+            frame: 0,
+            names: VarNames::new(),
         }
     }
 
+    pub fn names(&self) -> &VarNames {
+        &self.names
+    }
+
+    /* The frame counter, also written to SCALAR_FRAME */
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn set_frame(&mut self, frame: u32) {
+        self.frame = frame;
+        self.set_scalar(SCALAR_FRAME, frame as f32);
+    }
+
+    /* The slots the config declared for each of CLOCK_NAMES */
+    pub fn clock_slots(&self) -> [Option<usize>; 3] {
+        CLOCK_NAMES.map(|name| self.names.get(VarType::Scalar, name))
+    }
+
     pub fn set_scalar(&mut self, idx: usize, val: f32) {
         if idx < self.scalars.len() {
             self.scalars[idx] = val;
         }
     }
 
+    /* The number of slots in a pool */
+    pub fn pool_len(&self, ty: VarType) -> usize {
+        match ty {
+            VarType::Scalar => self.scalars.len(),
+            VarType::Position => self.positions.len(),
+            VarType::Color => self.colors.len(),
+            VarType::RColor => self.rcolors.len(),
+            VarType::Data => self.data.len(),
+            VarType::Gradient => self.gradients.len(),
+        }
+    }
+
     pub fn get_scalar(&self, idx: usize) -> f32 {
         self.scalars[idx]
     }
 
     pub fn set_position(&mut self, idx: usize, val: Position) {
-        self.positions[idx] = val;
+        if idx < self.positions.len() {
+            self.positions[idx] = val;
+        }
     }
 
     pub fn get_position(&self, idx: usize) -> &Position {
@@ -51,7 +240,9 @@ impl RenderState {
     }
 
     pub fn set_color(&mut self, idx: usize, val: Color) {
-        self.colors[idx] = val;
+        if idx < self.colors.len() {
+            self.colors[idx] = val;
+        }
     }
 
     pub fn get_color(&self, idx: usize) -> &Color {
@@ -59,7 +250,9 @@ impl RenderState {
     }
 
     pub fn set_rcolor(&mut self, idx: usize, val: RealColor) {
-        self.rcolors[idx] = val;
+        if idx < self.rcolors.len() {
+            self.rcolors[idx] = val;
+        }
     }
 
     pub fn get_rcolor(&self, idx: usize) -> &RealColor {
@@ -67,83 +260,126 @@ impl RenderState {
     }
 
     pub fn set_data(&mut self, idx: usize, val: Vec<u8>) {
-        self.data[idx] = val;
+        if idx < self.data.len() {
+            self.data[idx] = Arc::new(val);
+        }
     }
 
     pub fn get_data(&self, idx: usize) -> &Vec<u8> {
         &self.data[idx]
     }
 
-    pub fn debug(&self) {
-        print!("Scalars: {:?}\n", self.scalars);
-        print!("Positions: {:?}\n", self.positions);
-        print!("Colors: {:?}\n", self.colors);
-        print!("RealColors: {:?}\n", self.rcolors);
-        print!("Data: {:?}\n", self.data);
-    }
-
-    pub fn from_obj(&mut self, v: &JsonValue) {
-        let dict = match v {
-            JsonValue::Object(x) => x,
-            _ => panic!("Position is not an object"),
-        };
-
-        let list = match dict.get("float").expect("Missing 'float' initialization") {
-            JsonValue::Array(x) => x,
-            _ => panic!("Initialization for scalars is not a list"),
-        };
-        self.scalars.clear();
-        for o in list {
-            self.scalars
-                .push(o.as_f32().expect("Failed to interpret scalar value"));
+    pub fn set_gradient(&mut self, idx: usize, val: Gradient) {
+        if idx < self.gradients.len() {
+            self.gradients[idx] = Arc::new(val);
         }
+    }
 
-        let list = match dict
-            .get("position")
-            .expect("Missing 'position' initialization")
-        {
-            JsonValue::Array(x) => x,
-            _ => panic!("Initialization for positions is not a list"),
-        };
-        self.positions.clear();
-        for o in list {
-            self.positions.push(Position::from_obj(o));
-        }
+    pub fn get_gradient(&self, idx: usize) -> &Gradient {
+        &self.gradients[idx]
+    }
 
-        let list = match dict
-            .get("color")
-            .expect("Missing 'color' initialization")
-        {
-            JsonValue::Array(x) => x,
-            _ => panic!("Initialization for colors is not a list"),
-        };
-        self.colors.clear();
-        for o in list {
-            self.colors.push(Color::from_obj(o));
+    /* One slot as JSON, or None if it is out of range */
+    pub fn var_to_json(&self, ty: VarType, idx: usize) -> Option<JsonValue> {
+        match ty {
+            VarType::Scalar => self.scalars.get(idx).map(ToJson::to_json),
+            VarType::Position => self.positions.get(idx).map(ToJson::to_json),
+            VarType::Color => self.colors.get(idx).map(ToJson::to_json),
+            VarType::RColor => self.rcolors.get(idx).map(ToJson::to_json),
+            VarType::Data => self.data.get(idx).map(|d| d.to_json()),
+            VarType::Gradient => self.gradients.get(idx).map(|g| g.to_json()),
         }
+    }
 
-        let list = match dict
-            .get("rcolor")
-            .expect("Missing 'rcolor' initialization")
-        {
-            JsonValue::Array(x) => x,
-            _ => panic!("Initialization for rcolors is not a list"),
-        };
-        self.rcolors.clear();
-        for o in list {
-            self.rcolors.push(RealColor::from_obj(o));
+    /*
+     * The current state in the same form as the vars stanza, so it can be fed
+     * back in a config. Named slots are written as `{"name", "value"}`.
+     */
+    pub fn to_json(&self) -> JsonValue {
+        let mut vars = JsonValue::new_object();
+        for ty in VarType::ALL {
+            let pool: Vec<JsonValue> = (0..self.pool_len(ty))
+                .filter_map(|i| {
+                    let value = self.var_to_json(ty, i)?;
+                    Some(match self.names.name_of(ty, i) {
+                        Some(name) => json::object! {name: name, value: value},
+                        None => value,
+                    })
+                })
+                .collect();
+            vars[ty.name()] = pool.into();
         }
+        vars
+    }
 
-        let list = match dict
-            .get("data")
-            .expect("Missing 'data' initialization")
-        {
-            JsonValue::Array(x) => x,
-            _ => panic!("Initialization for data is not a list"),
-        };
-        self.data.clear();
-        for o in list {
-            self.data.push(Data::from_obj(o));
+    pub fn debug(&self) {
+        println!("Scalars: {:?}", self.scalars);
+        println!("Positions: {:?}", self.positions);
+        println!("Colors: {:?}", self.colors);
+        println!("RealColors: {:?}", self.rcolors);
+        println!("Data: {:?}", self.data);
+        println!("Gradients: {:?}", self.gradients);
+    }
+
+    /*
+     * Parses one pool of the vars stanza. Each entry is either a bare value or
+     * an object of the form `{"name": "hue_speed", "value": 0.015}`, where the
+     * name is optional.
+     */
+    fn pool_from_obj<T: FromJson>(
+        dict: &ConfigObj,
+        ty: VarType,
+        names: &mut VarNames,
+    ) -> Result<Vec<T>, ConfigError> {
+        let path = dict.key_path(ty.name());
+
+        let mut pool = Vec::<T>::new();
+        for (i, v) in dict.array(ty.name())?.iter().enumerate() {
+            let entry_path = elem_path(&path, i);
+
+            let value = match v {
+                JsonValue::Object(x) if x.get("value").is_some() => {
+                    let entry = ConfigObj::new(v, &entry_path)?;
+                    if entry.opt("name").is_some() {
+                        let name = entry.str("name")?;
+                        if !names.insert(ty, name, i) {
+                            return Err(entry.err("name", format!("duplicate name '{name}'")));
+                        }
+                    }
+                    T::from_obj(entry.value("value")?, &key_path(&entry_path, "value"))?
+                }
+                _ => T::from_obj(v, &entry_path)?,
+            };
+            pool.push(value);
         }
+        names.set_len(ty, pool.len());
+
+        Ok(pool)
+    }
+
+    pub fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        let dict = ConfigObj::new(v, path)?;
+        let mut names = VarNames::new();
+
+        Ok(RenderState {
+            scalars: Self::pool_from_obj(&dict, VarType::Scalar, &mut names)?,
+            positions: Self::pool_from_obj(&dict, VarType::Position, &mut names)?,
+            colors: Self::pool_from_obj(&dict, VarType::Color, &mut names)?,
+            rcolors: Self::pool_from_obj(&dict, VarType::RColor, &mut names)?,
+            data: Self::pool_from_obj::<Data>(&dict, VarType::Data, &mut names)?
+                .into_iter()
+                .map(Arc::new)
+                .collect(),
+            // Older configs have no gradient pool
+            gradients: match dict.opt(VarType::Gradient.name()) {
+                Some(_) => Self::pool_from_obj::<Gradient>(&dict, VarType::Gradient, &mut names)?
+                    .into_iter()
+                    .map(Arc::new)
+                    .collect(),
+                None => Vec::new(),
+            },
+            frame: 0,
+            names,
+        })
     }
 }
@@ -0,0 +1,304 @@
+use std::ops::Range;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::blocks::dither::Residual;
+use crate::constants;
+use crate::graph::{compile, Graph};
+use crate::output::OutputStage;
+use crate::render_block::{RenderBlock, RenderState, ELAPSED_PERIOD, SCALAR_X, SCALAR_Y};
+use crate::transition::{blend, Transition};
+use crate::var_types::{Color, RealColor};
+
+/* One frame's work for a render thread, handed back once it is rendered */
+struct Job {
+    state: RenderState,
+    out: Vec<RealColor>,
+}
+
+/*
+ * A render thread with its own copy of the pixel blocks, which renders the
+ * same columns every frame. The thread exits once `jobs` is dropped.
+ */
+struct Worker {
+    xs: Range<usize>,
+    jobs: mpsc::Sender<Job>,
+    done: mpsc::Receiver<Job>,
+    // The job's buffers while the thread is idle
+    spare: Option<Job>,
+}
+
+/* The render loop's view of time for one frame */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameTime {
+    pub frame: u32,
+    /* Seconds since rendering started, which days of running would take
+     * beyond an f32's precision */
+    pub elapsed: f64,
+    /* Seconds since the previous frame */
+    pub dt: f32,
+    /* Seconds since local midnight */
+    pub time_of_day: f32,
+}
+
+/*
+ * One config's graph and state, along with a worker per render thread.
+ *
+ * With more than one thread, the columns are split into contiguous ranges
+ * and each range is rendered by a worker with its own copy of the state and
+ * blocks. Workers start every frame from the shared state, so anything set
+ * between frames (config, HTTP updates, the frame counter) is seen by all of
+ * them. Afterwards the state of the worker that rendered the final pixel is
+ * carried forward.
+ *
+ * This only matches the serial loop when every pixel can be rendered without
+ * the ones before it, so graphs marked serial by graph::compile get no
+ * workers and render on the calling thread.
+ */
+struct Scene {
+    state: RenderState,
+    graph: Graph,
+    output: OutputStage,
+    clock: [Option<usize>; 3],
+    workers: Vec<Worker>,
+}
+
+/* The outgoing scene while a transition is in progress */
+struct Fade {
+    old: Scene,
+    transition: Transition,
+    /* Seconds since the transition started */
+    elapsed: f32,
+}
+
+/*
+ * Runs a block list over every pixel. Pixels are produced in px_idx order,
+ * i.e. x-major with STRING_COUNT pixels per column. Blocks that don't vary
+ * per pixel are run once per frame beforehand (see graph::compile).
+ *
+ * Each scene's output stage turns its pixels into drive levels, which are
+ * quantized to the framebuffer once everything else is done.
+ *
+ * When a config arrives with a transition, the previous scene keeps rendering
+ * alongside the new one and the two are blended per pixel until the
+ * transition completes. Set messages always go to the new scene.
+ */
+pub struct Renderer {
+    scene: Scene,
+    fade: Option<Fade>,
+    // Drive levels of both scenes, kept to avoid allocating every frame
+    drive: Vec<RealColor>,
+    old_drive: Vec<RealColor>,
+    // Rounding error kept by the output stage for error diffusion
+    residual: Residual,
+
+    threads: usize,
+}
+
+/* Render columns `xs` into `out`, which holds STRING_COUNT levels per column */
+fn render_columns(
+    state: &mut RenderState,
+    blocks: &mut [Box<dyn RenderBlock>],
+    output: &OutputStage,
+    xs: Range<usize>,
+    out: &mut [RealColor],
+) {
+    for (col, x) in out.chunks_mut(constants::STRING_COUNT).zip(xs) {
+        state.set_scalar(SCALAR_X, x as f32);
+        for (y, px) in col.iter_mut().enumerate() {
+            state.set_scalar(SCALAR_Y, y as f32);
+
+            for block in blocks.iter_mut() {
+                block.as_mut().execute(state);
+            }
+
+            *px = output.drive(state);
+        }
+    }
+}
+
+impl Scene {
+    fn new(
+        state: RenderState,
+        blocks: Vec<Box<dyn RenderBlock>>,
+        output: OutputStage,
+        threads: usize,
+    ) -> Self {
+        let graph = compile(blocks);
+
+        let mut workers = Vec::new();
+        if threads > 1 && !graph.serial {
+            let cols = constants::LED_COUNT.div_ceil(threads);
+            for start in (0..constants::LED_COUNT).step_by(cols) {
+                let xs = start..(start + cols).min(constants::LED_COUNT);
+                workers.push(Worker::spawn(&state, &graph, output, xs));
+            }
+        }
+
+        Scene {
+            clock: state.clock_slots(),
+            state,
+            graph,
+            output,
+            workers,
+        }
+    }
+
+    /* Whether there is anything to render, i.e. an output color */
+    fn is_empty(&self) -> bool {
+        self.state.pool_len(self.output.source()) == 0
+    }
+
+    fn render(&mut self, time: &FrameTime, pixels: &mut [RealColor]) {
+        self.state.set_frame(time.frame);
+        let elapsed = (time.elapsed % ELAPSED_PERIOD) as f32;
+        let clock = [elapsed, time.dt, time.time_of_day];
+        for (slot, value) in self.clock.into_iter().zip(clock) {
+            if let Some(i) = slot {
+                self.state.set_scalar(i, value);
+            }
+        }
+
+        for block in self.graph.frame_blocks.iter_mut() {
+            block.as_mut().execute(&mut self.state);
+        }
+
+        if self.workers.is_empty() {
+            let blocks = &mut self.graph.pixel_blocks;
+            let xs = 0..constants::LED_COUNT;
+            render_columns(&mut self.state, blocks, &self.output, xs, pixels);
+            return;
+        }
+
+        for worker in self.workers.iter_mut() {
+            let mut job = worker.spare.take().expect("render thread is busy");
+            job.state.clone_from(&self.state);
+            worker.jobs.send(job).expect("render thread exited");
+        }
+
+        for worker in self.workers.iter_mut() {
+            let job = worker.done.recv().expect("render thread exited");
+            let start = worker.xs.start * constants::STRING_COUNT;
+            pixels[start..start + job.out.len()].copy_from_slice(&job.out);
+            worker.spare = Some(job);
+        }
+
+        // The last worker rendered the final pixel
+        if let Some(job) = self.workers.last().and_then(|w| w.spare.as_ref()) {
+            self.state.clone_from(&job.state);
+        }
+    }
+}
+
+impl Worker {
+    fn spawn(state: &RenderState, graph: &Graph, output: OutputStage, xs: Range<usize>) -> Self {
+        let (jobs, jobs_rx) = mpsc::channel::<Job>();
+        let (done_tx, done) = mpsc::channel();
+        let mut blocks = graph.pixel_blocks.clone();
+        let range = xs.clone();
+
+        thread::spawn(move || {
+            while let Ok(mut job) = jobs_rx.recv() {
+                let Job { state, out } = &mut job;
+                render_columns(state, &mut blocks, &output, range.clone(), out);
+                if done_tx.send(job).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Worker {
+            spare: Some(Job {
+                state: state.clone(),
+                out: vec![RealColor::default(); xs.len() * constants::STRING_COUNT],
+            }),
+            xs,
+            jobs,
+            done,
+        }
+    }
+}
+
+impl Renderer {
+    pub fn new(state: RenderState, blocks: Vec<Box<dyn RenderBlock>>, threads: usize) -> Self {
+        let threads = threads.clamp(1, constants::LED_COUNT);
+
+        Renderer {
+            scene: Scene::new(state, blocks, OutputStage::default(), threads),
+            fade: None,
+            drive: Vec::new(),
+            old_drive: Vec::new(),
+            residual: Residual::new(),
+            threads,
+        }
+    }
+
+    /*
+     * Replaces the running config. With a transition, the current scene fades
+     * out underneath the new one. A transition that is already in progress is
+     * cut short, dropping the scene it was fading out.
+     */
+    pub fn set_graph(
+        &mut self,
+        state: RenderState,
+        blocks: Vec<Box<dyn RenderBlock>>,
+        output: OutputStage,
+        transition: Option<Transition>,
+    ) {
+        let scene = Scene::new(state, blocks, output, self.threads);
+        let old = std::mem::replace(&mut self.scene, scene);
+        // The old config's rounding error means nothing to the new one
+        self.residual = Residual::new();
+
+        self.fade = match transition {
+            Some(transition) if !old.is_empty() => Some(Fade {
+                old,
+                transition,
+                elapsed: 0.0,
+            }),
+            _ => None,
+        };
+    }
+
+    /* The number of (per-frame, per-pixel) blocks */
+    pub fn stage_sizes(&self) -> (usize, usize) {
+        let graph = &self.scene.graph;
+        (graph.frame_blocks.len(), graph.pixel_blocks.len())
+    }
+
+    /* Whether the running config has to render on one thread */
+    pub fn is_serial(&self) -> bool {
+        self.scene.graph.serial
+    }
+
+    pub fn state(&self) -> &RenderState {
+        &self.scene.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut RenderState {
+        &mut self.scene.state
+    }
+
+    /* Renders one frame into `pixels`, which must hold PIXEL_COUNT colors */
+    pub fn render(&mut self, time: &FrameTime, pixels: &mut [Color]) {
+        self.drive.resize(pixels.len(), RealColor::default());
+        self.scene.render(time, &mut self.drive);
+
+        if let Some(fade) = self.fade.as_mut() {
+            // The first frame of a transition is entirely the old scene
+            let t = fade.elapsed / fade.transition.duration;
+            fade.elapsed += time.dt;
+
+            self.old_drive.resize(pixels.len(), RealColor::default());
+            fade.old.render(time, &mut self.old_drive);
+            blend(&self.old_drive, &mut self.drive, fade.transition.curve.weight(t));
+
+            if t >= 1.0 {
+                self.fade = None;
+            }
+        }
+
+        let output = &self.scene.output;
+        output.quantize(&self.drive, time.frame, &mut self.residual, pixels);
+    }
+}
@@ -7,17 +7,23 @@ use hyper::{
 use http_body_util::{
     Empty,
     Full,
+    Limited,
+    LengthLimitError,
     combinators::BoxBody,
     BodyExt,
 };
+use image::ImageReader;
 use json::JsonValue;
+use std::collections::HashMap;
 use std::future::Future;
+use std::io::Cursor;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio::{
     sync::broadcast::Sender,
     sync::broadcast::error::*,
+    sync::{mpsc, oneshot, watch},
     net::TcpListener,
 };
 use hyper_util::{
@@ -26,10 +32,20 @@ use hyper_util::{
     server::conn::auto,
 };
 
-use crate::modular_msg::{ModularMessage, Settable};
+use crate::config::{ConfigError, ConfigObj};
+use crate::constants;
+use crate::control_ws::control_ws;
+use crate::gradient::Gradient;
+use crate::modular_msg::{check_names, ModularMessage, ModularQuery, RenderLink, Settable, VarId, VarMsg};
 use crate::led_msg::LedMessage;
+use crate::player_msg::{limits, PlayerMessage, RawFormat};
+use crate::playlist_msg::PlaylistMessage;
+use crate::preview::{preview_page, preview_ws, PreviewFrame};
 use crate::var_types::*;
 
+/* Compressed images up to MAX_SIDE fit comfortably in this */
+const MAX_UPLOAD: usize = 32 << 20;
+
 // We create some utility functions to make Empty and Full bodies
 // fit our broadened Response body type.
 fn empty() -> BoxBody<Bytes, hyper::Error> {
@@ -43,11 +59,20 @@ fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
         .boxed()
 }
 
+/* Checks a config before it is sent to the render loop, returning any warnings */
+pub type ConfigValidator = fn(&json::object::Object) -> Result<Vec<ConfigError>, ConfigError>;
 
 #[derive(Debug, Clone)]
 pub struct Svc {
     led_cmd: Arc<Sender<LedMessage>>,
     mod_cmd: Arc<Sender<ModularMessage>>,
+    preview: Option<Arc<Sender<PreviewFrame>>>,
+    validate: Option<ConfigValidator>,
+    playlist: Option<Arc<mpsc::Sender<PlaylistMessage>>>,
+    query: Option<Arc<mpsc::Sender<ModularQuery>>>,
+    player: Option<Arc<mpsc::Sender<PlayerMessage>>>,
+    // The variable names in the running config, to reject unknown ones
+    names: Option<watch::Receiver<VarNames>>,
 }
 
 fn mk_response(status: StatusCode, s: String) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
@@ -57,6 +82,13 @@ fn mk_response(status: StatusCode, s: String) -> Result<Response<BoxBody<Bytes,
         .unwrap())
 }
 
+fn mk_json(value: JsonValue) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .body(full(value.dump()))
+        .unwrap())
+}
+
 fn mk_status(status: StatusCode) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
     Ok(Response::builder()
         .status(status)
@@ -64,6 +96,55 @@ fn mk_status(status: StatusCode) -> Result<Response<BoxBody<Bytes, hyper::Error>
         .unwrap())
 }
 
+/* Splits a query string into its key=value pairs. Values are not unescaped. */
+fn parse_query(query: Option<&str>) -> HashMap<String, String> {
+    query.unwrap_or("")
+        .split('&')
+        .filter(|kv| !kv.is_empty())
+        .map(|kv| match kv.split_once('=') {
+            Some((k, v)) => (k.to_string(), v.to_string()),
+            None => (kv.to_string(), String::new()),
+        })
+        .collect()
+}
+
+/* Scalar and data slots may be given by index or by name */
+fn parse_var_id(s: &str) -> VarId {
+    s.parse().map_or_else(|_| VarId::Name(s.to_string()), VarId::Index)
+}
+
+/*
+ * Decodes a PNG, JPEG or GIF into the rows ImageLookup reads, scaling it to
+ * fit within `resize` if given. Either way it is shrunk to fit within
+ * MAX_IMAGE_SIDE, since ImageLookup can't index anything larger. Colors are
+ * scaled by `brightness`. Without `alpha` the pixels are RGB with
+ * transparency composited over black.
+ */
+fn decode_image(bytes: &[u8], resize: Option<(u32, u32)>, brightness: f32, alpha: bool) -> Result<(u32, u32, Vec<u8>), String> {
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format().map_err(|why| why.to_string())?;
+    reader.limits(limits());
+    let mut img = reader.decode().map_err(|why| why.to_string())?;
+
+    let max = constants::MAX_IMAGE_SIDE as u32;
+    let (w, h) = resize.map_or((max, max), |(w, h)| (w.min(max), h.min(max)));
+    if resize.is_some() || img.width() > w || img.height() > h {
+        img = img.resize(w, h, image::imageops::FilterType::Triangle);
+    }
+
+    let img = img.to_rgba8();
+    let mut data = Vec::with_capacity(img.width() as usize * img.height() as usize * 4);
+    for p in img.pixels() {
+        let [r, g, b, a] = p.0;
+        let k = if alpha { brightness } else { brightness * a as f32 / 255.0 };
+        data.extend([r, g, b].map(|c| (c as f32 * k).round().clamp(0.0, 255.0) as u8));
+        if alpha {
+            data.push(a);
+        }
+    }
+
+    Ok((img.width(), img.height(), data))
+}
+
 impl Svc {
     // Note that these are not methods that consume &self since that introduces
     // lifetime issues for the future (?).
@@ -93,10 +174,25 @@ impl Svc {
     }
 
     /* Parses the full config and passes it to the render block */
-    async fn set_config(req: Request<Incoming>, mod_cmd: Arc<Sender<ModularMessage>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    async fn set_config(req: Request<Incoming>, mod_cmd: Arc<Sender<ModularMessage>>, validate: Option<ConfigValidator>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
         Self::set_generic(req, |json_obj| {
+            // Reject bad configs here so the running animation is left alone
+            let warnings = match validate.map(|f| f(&json_obj)) {
+                Some(Err(why)) => {
+                    println!("Rejected config: {why}");
+                    return mk_response(StatusCode::BAD_REQUEST, why.to_string());
+                }
+                Some(Ok(warnings)) => warnings,
+                None => Vec::new(),
+            };
+
             match mod_cmd.send(ModularMessage::Config(json_obj)) {
-                Ok(_) => mk_status(StatusCode::OK),
+                Ok(_) => {
+                    // Warnings don't stop the config but are worth reporting
+                    let text: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+                    text.iter().for_each(|w| println!("Config warning: {w}"));
+                    mk_response(StatusCode::OK, text.join("\n"))
+                }
                 Err(why) => {
                     println!("Failed to send config: {why}");
                     mk_status(StatusCode::INTERNAL_SERVER_ERROR)
@@ -105,38 +201,145 @@ impl Svc {
         }).await
     }
 
-    /* Parses the index and value for setting a value */
+    /* Parses the index or name and value for setting a value */
     async fn set_value<F, T>(req: Request<Incoming>, func: F) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>
-        where F: FnOnce(usize, &JsonValue) -> Result<usize, SendError<T>> {
+        where F: FnOnce(VarId, &JsonValue) -> Result<Result<usize, SendError<T>>, ConfigError> {
         Self::set_generic(req, |data| {
-            let index = data
-                .get("index").expect("Missing index parameter")
-                .as_usize().expect("Index parameter must be an integer");
-            let obj = data
-                .get("value").expect("Missing value parameter");
+            let dict = ConfigObj::from_dict(&data, "");
+            let parsed = VarId::from_obj(&dict).and_then(|id| {
+                func(id, dict.value("value")?)
+            });
 
-            match func(index, obj) {
-                Ok(_) => {
+            match parsed {
+                Ok(Ok(_)) => {
                     mk_status(StatusCode::OK)
                 },
-                Err(why) => {
+                Ok(Err(why)) => {
                     println!("Failed to send scalar: {why}");
                     mk_status(StatusCode::INTERNAL_SERVER_ERROR)
                 }
+                Err(why) => {
+                    println!("Rejected value: {why}");
+                    mk_response(StatusCode::BAD_REQUEST, why.to_string())
+                }
             }
         }).await
     }
 
     /* Uses the FromJson trait to parse the value and the Settable trait to generate
      * the proper message variant, then sends it. */
-    async fn set_object<T: FromJson + Settable>(req: Request<Incoming>, mod_cmd: Arc<Sender<ModularMessage>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-        Self::set_value(req, |index, obj| {
-            let value = T::from_obj(obj);
-            //println!("Set {} idx {index}", std::any::type_name::<T>());
-            mod_cmd.send(T::into_message(index, value))
+    async fn set_object<T: FromJson + Settable>(req: Request<Incoming>, mod_cmd: Arc<Sender<ModularMessage>>, names: Option<watch::Receiver<VarNames>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        Self::set_value(req, |id, obj| {
+            let value = T::from_obj(obj, "value")?;
+            //println!("Set {} id {id}", std::any::type_name::<T>());
+            let msg = T::into_message(id, value);
+            check_names(&names, &msg, "")?;
+            Ok(mod_cmd.send(msg))
         }).await
     }
 
+    /* Sends a list of typed assignments as one batch, see ModularMessage::set_from_obj */
+    async fn set_vars(req: Request<Incoming>, mod_cmd: Arc<Sender<ModularMessage>>, names: Option<watch::Receiver<VarNames>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        Self::set_generic(req, |data| {
+            let dict = ConfigObj::from_dict(&data, "");
+            let parsed = ModularMessage::batch_from_obj(&dict, "vars")
+                .and_then(|batch| check_names(&names, &batch, "vars").map(|_| batch));
+            match parsed {
+                Ok(batch) => match mod_cmd.send(batch) {
+                    Ok(_) => mk_status(StatusCode::OK),
+                    Err(why) => {
+                        println!("Failed to send batch: {why}");
+                        mk_status(StatusCode::INTERNAL_SERVER_ERROR)
+                    }
+                },
+                Err(why) => {
+                    println!("Rejected batch: {why}");
+                    mk_response(StatusCode::BAD_REQUEST, why.to_string())
+                }
+            }
+        }).await
+    }
+
+    /*
+     * Decodes an image body into a data slot and sets its size scalars in one
+     * batch, e.g. /upload_image?data=0&width=4&height=5&resize=64x32. Slots
+     * are indices or names. resize fits the image within WxH keeping its
+     * aspect ratio, up to 1024x1024, which is also the default for larger
+     * images. brightness scales the colors, 1.0 by default, and format=rgba
+     * keeps the alpha channel for ImageLookup's rgba format.
+     */
+    async fn upload_image(req: Request<Incoming>, mod_cmd: Arc<Sender<ModularMessage>>, names: Option<watch::Receiver<VarNames>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        let params = parse_query(req.uri().query());
+
+        let parsed = (|| {
+            let data = params.get("data").ok_or("missing data slot")?;
+            let resize = match params.get("resize") {
+                Some(s) => {
+                    let size = s.split_once('x')
+                        .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                        .filter(|(w, h)| *w > 0 && *h > 0);
+                    Some(size.ok_or("resize must be WIDTHxHEIGHT")?)
+                }
+                None => None,
+            };
+            let brightness = match params.get("brightness") {
+                Some(s) => s.parse::<f32>().ok().filter(|b| *b >= 0.0).ok_or("brightness must be a non-negative number")?,
+                None => 1.0,
+            };
+            let alpha = match params.get("format").map(String::as_str) {
+                Some("rgb") | None => false,
+                Some("rgba") => true,
+                Some(_) => return Err("format must be rgb or rgba"),
+            };
+            Ok::<_, &str>((parse_var_id(data), resize, brightness, alpha))
+        })();
+        let (data_id, resize, brightness, alpha) = match parsed {
+            Ok(x) => x,
+            Err(why) => {
+                println!("Rejected image upload: {why}");
+                return mk_response(StatusCode::BAD_REQUEST, why.to_string());
+            }
+        };
+
+        let bytes = match Limited::new(req.into_body(), MAX_UPLOAD).collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(why) => {
+                println!("Rejected image upload: {why}");
+                let status = if why.is::<LengthLimitError>() { StatusCode::PAYLOAD_TOO_LARGE } else { StatusCode::BAD_REQUEST };
+                return mk_response(status, why.to_string());
+            }
+        };
+        let decoded = tokio::task::spawn_blocking(move || decode_image(&bytes, resize, brightness, alpha)).await;
+        let (width, height, data) = match decoded {
+            Ok(Ok(x)) => x,
+            Ok(Err(why)) => {
+                println!("Couldn't decode image: {why}");
+                return mk_response(StatusCode::BAD_REQUEST, why);
+            }
+            Err(_) => return mk_status(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+
+        let mut batch = vec![ModularMessage::SetData(VarMsg { id: data_id, value: data })];
+        for (key, value) in [("width", width), ("height", height)] {
+            if let Some(s) = params.get(key) {
+                batch.push(ModularMessage::SetScalar(VarMsg { id: parse_var_id(s), value: value as f32 }));
+            }
+        }
+        let batch = ModularMessage::Batch(batch);
+        if let Err(why) = check_names(&names, &batch, "") {
+            println!("Rejected image upload: {why}");
+            return mk_response(StatusCode::BAD_REQUEST, why.to_string());
+        }
+
+        match mod_cmd.send(batch) {
+            Ok(_) => mk_json(json::object! { width: width, height: height }),
+            Err(why) => {
+                println!("Failed to send image: {why}");
+                mk_status(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
     async fn set_white_led(req: Request<Incoming>, led_cmd: Arc<Sender<LedMessage>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
         let bytes = req.into_body().collect().await.unwrap().to_bytes();
         let json_str = String::from_utf8(bytes.into_iter().collect()).expect("");
@@ -174,6 +377,247 @@ impl Svc {
             }
         }
     }
+
+    /* Upgrades to a WebSocket for streaming set requests, see control_ws.rs */
+    async fn get_control_ws(mut req: Request<Incoming>, mod_cmd: Arc<Sender<ModularMessage>>, names: Option<watch::Receiver<VarNames>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        if !hyper_tungstenite::is_upgrade_request(&req) {
+            return mk_status(StatusCode::BAD_REQUEST);
+        }
+
+        match hyper_tungstenite::upgrade(&mut req, None) {
+            Ok((response, websocket)) => {
+                tokio::task::spawn(control_ws(websocket, mod_cmd, names));
+                Ok(response.map(|b| b.map_err(|never| match never {}).boxed()))
+            }
+            Err(why) => {
+                println!("WebSocket upgrade failure: {why}");
+                mk_status(StatusCode::BAD_REQUEST)
+            }
+        }
+    }
+
+    /* Serves the preview page, which connects back to /preview/ws */
+    async fn get_preview(preview: Option<Arc<Sender<PreviewFrame>>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        if preview.is_none() {
+            return mk_status(StatusCode::NOT_FOUND);
+        }
+
+        Ok(Response::builder()
+            .header("Content-Type", "text/html")
+            .body(full(preview_page()))
+            .unwrap())
+    }
+
+    /* Upgrades to a WebSocket and streams flushed frames to the client */
+    async fn get_preview_ws(mut req: Request<Incoming>, preview: Option<Arc<Sender<PreviewFrame>>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        let preview = match preview {
+            Some(x) => x,
+            None => return mk_status(StatusCode::NOT_FOUND),
+        };
+
+        if !hyper_tungstenite::is_upgrade_request(&req) {
+            return mk_status(StatusCode::BAD_REQUEST);
+        }
+
+        match hyper_tungstenite::upgrade(&mut req, None) {
+            Ok((response, websocket)) => {
+                tokio::task::spawn(preview_ws(websocket, preview.subscribe()));
+                Ok(response.map(|b| b.map_err(|never| match never {}).boxed()))
+            }
+            Err(why) => {
+                println!("WebSocket upgrade failure: {why}");
+                mk_status(StatusCode::BAD_REQUEST)
+            }
+        }
+    }
+
+    /* Passes a command on to the playlist or player, 404 if this binary has none */
+    async fn send_cmd<T: Send>(tx: Option<Arc<mpsc::Sender<T>>>, msg: T) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        let tx = match tx {
+            Some(x) => x,
+            None => return mk_status(StatusCode::NOT_FOUND),
+        };
+
+        match tx.send(msg).await {
+            Ok(_) => mk_status(StatusCode::OK),
+            Err(why) => {
+                println!("Failed to send command: {why}");
+                mk_status(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
+    /* Describes the schedule and what is playing as JSON */
+    async fn get_playlist(playlist: Option<Arc<mpsc::Sender<PlaylistMessage>>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        let playlist = match playlist {
+            Some(x) => x,
+            None => return mk_status(StatusCode::NOT_FOUND),
+        };
+
+        let (tx, rx) = oneshot::channel();
+        if playlist.send(PlaylistMessage::Status(tx)).await.is_err() {
+            return mk_status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        match rx.await {
+            Ok(status) => mk_json(status),
+            Err(_) => mk_status(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    /* Reads a request body that must be a JSON object */
+    async fn read_object(req: Request<Incoming>) -> Result<JsonValue, String> {
+        let bytes = req.into_body().collect().await.map_err(|why| why.to_string())?.to_bytes();
+        let json_str = String::from_utf8(bytes.into_iter().collect()).map_err(|why| why.to_string())?;
+
+        match json::parse(&json_str) {
+            Ok(data) if data.is_object() => Ok(data),
+            Ok(_) => Err("JSON is not an object".to_string()),
+            Err(why) => Err(format!("JSON parse failure: {why}")),
+        }
+    }
+
+    /*
+     * Loads and plays a file from the media directory, e.g.
+     * {"file": "clip.gif", "loop": false}. Raw RGB files also need
+     * {"raw": {"width", "height", "fps"}}. Any of the /player/set settings may
+     * be given too.
+     */
+    async fn player_open(req: Request<Incoming>, player: Option<Arc<mpsc::Sender<PlayerMessage>>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        let player = match player {
+            Some(x) => x,
+            None => return mk_status(StatusCode::NOT_FOUND),
+        };
+
+        let data = match Self::read_object(req).await {
+            Ok(data) => data,
+            Err(why) => {
+                println!("{why}");
+                return mk_response(StatusCode::BAD_REQUEST, why);
+            }
+        };
+
+        let dict = match ConfigObj::new(&data, "") {
+            Ok(dict) => dict,
+            Err(why) => return mk_response(StatusCode::BAD_REQUEST, why.to_string()),
+        };
+        let parsed = (|| {
+            let path = dict.str("file")?.to_string();
+            let raw = match dict.opt("raw") {
+                Some(v) => Some(RawFormat::from_obj(v, &dict.key_path("raw"))?),
+                None => None,
+            };
+            Ok::<_, ConfigError>((path, raw, PlayerMessage::settings_from_obj(&dict)?))
+        })();
+        let (path, raw, settings) = match parsed {
+            Ok(x) => x,
+            Err(why) => {
+                println!("Rejected player request: {why}");
+                return mk_response(StatusCode::BAD_REQUEST, why.to_string());
+            }
+        };
+
+        let (tx, rx) = oneshot::channel();
+        if player.send(PlayerMessage::Open { path, raw, reply: tx }).await.is_err() {
+            return mk_status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        match rx.await {
+            Ok(Ok(())) => {}
+            Ok(Err(why)) => return mk_response(StatusCode::BAD_REQUEST, why),
+            Err(_) => return mk_status(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+
+        for msg in settings {
+            if player.send(msg).await.is_err() {
+                return mk_status(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+        mk_status(StatusCode::OK)
+    }
+
+    /* Changes any of {"position", "rate", "loop"} on the playing file */
+    async fn player_set(req: Request<Incoming>, player: Option<Arc<mpsc::Sender<PlayerMessage>>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        let player = match player {
+            Some(x) => x,
+            None => return mk_status(StatusCode::NOT_FOUND),
+        };
+
+        let data = match Self::read_object(req).await {
+            Ok(data) => data,
+            Err(why) => {
+                println!("{why}");
+                return mk_response(StatusCode::BAD_REQUEST, why);
+            }
+        };
+
+        let settings = match ConfigObj::new(&data, "").and_then(|dict| PlayerMessage::settings_from_obj(&dict)) {
+            Ok(x) => x,
+            Err(why) => {
+                println!("Rejected player request: {why}");
+                return mk_response(StatusCode::BAD_REQUEST, why.to_string());
+            }
+        };
+
+        for msg in settings {
+            if player.send(msg).await.is_err() {
+                return mk_status(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+        mk_status(StatusCode::OK)
+    }
+
+    /* Describes the loaded file and playback settings as JSON */
+    async fn get_player(player: Option<Arc<mpsc::Sender<PlayerMessage>>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        let player = match player {
+            Some(x) => x,
+            None => return mk_status(StatusCode::NOT_FOUND),
+        };
+
+        let (tx, rx) = oneshot::channel();
+        if player.send(PlayerMessage::Status(tx)).await.is_err() {
+            return mk_status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        match rx.await {
+            Ok(status) => mk_json(status),
+            Err(_) => mk_status(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    /* Asks the render loop for part of its state, 404 if there is no such thing */
+    async fn get_state<F>(query: Option<Arc<mpsc::Sender<ModularQuery>>>, func: F) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>
+        where F: FnOnce(oneshot::Sender<Option<JsonValue>>) -> ModularQuery {
+        let query = match query {
+            Some(x) => x,
+            None => return mk_status(StatusCode::NOT_FOUND),
+        };
+
+        let (tx, rx) = oneshot::channel();
+        if let Err(why) = query.send(func(tx)).await {
+            println!("Failed to send query: {why}");
+            return mk_status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        match rx.await {
+            Ok(Some(value)) => mk_json(value),
+            Ok(None) => mk_status(StatusCode::NOT_FOUND),
+            Err(_) => mk_status(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    /* Handles /vars/{type}/{index}, where the index may also be a name */
+    async fn get_var(path: String, query: Option<Arc<mpsc::Sender<ModularQuery>>>) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        let parts: Vec<&str> = path.trim_start_matches("/vars/").split('/').collect();
+        let (ty, id) = match parts[..] {
+            [ty, id] if !id.is_empty() => match VarType::from_name(ty) {
+                Some(ty) => (ty, parse_var_id(id)),
+                None => return mk_status(StatusCode::NOT_FOUND),
+            },
+            _ => return mk_status(StatusCode::NOT_FOUND),
+        };
+
+        Self::get_state(query, |tx| ModularQuery::Var(ty, id, tx)).await
+    }
 }
 
 impl Service<Request<Incoming>> for Svc {
@@ -190,34 +634,99 @@ impl Service<Request<Incoming>> for Svc {
             // TODO: look at BoxFuture and explicitly define the lifetime of the future
             // as the lifetime of the server (or the lifetime of mod_cmd?)
             (&Method::POST, "/set_config") => {
-                return Box::pin(Self::set_config(req, self.mod_cmd.clone()))
+                Box::pin(Self::set_config(req, self.mod_cmd.clone(), self.validate))
             }
             (&Method::POST, "/set_scalar") => {
-                return Box::pin(Self::set_object::<f32>(req, self.mod_cmd.clone()))
+                Box::pin(Self::set_object::<f32>(req, self.mod_cmd.clone(), self.names.clone()))
             }
             (&Method::POST, "/set_position") => {
-                return Box::pin(Self::set_object::<Position>(req, self.mod_cmd.clone()))
+                Box::pin(Self::set_object::<Position>(req, self.mod_cmd.clone(), self.names.clone()))
             }
             (&Method::POST, "/set_color") => {
-                return Box::pin(Self::set_object::<Color>(req, self.mod_cmd.clone()))
+                Box::pin(Self::set_object::<Color>(req, self.mod_cmd.clone(), self.names.clone()))
             }
             (&Method::POST, "/set_rcolor") => {
-                return Box::pin(Self::set_object::<RealColor>(req, self.mod_cmd.clone()))
+                Box::pin(Self::set_object::<RealColor>(req, self.mod_cmd.clone(), self.names.clone()))
             }
             (&Method::POST, "/set_data") => {
-                return Box::pin(Self::set_object::<Data>(req, self.mod_cmd.clone()))
+                Box::pin(Self::set_object::<Data>(req, self.mod_cmd.clone(), self.names.clone()))
+            }
+            (&Method::POST, "/set_gradient") => {
+                Box::pin(Self::set_object::<Gradient>(req, self.mod_cmd.clone(), self.names.clone()))
+            }
+            (&Method::POST, "/set_vars") => {
+                Box::pin(Self::set_vars(req, self.mod_cmd.clone(), self.names.clone()))
+            }
+            (&Method::POST, "/upload_image") => {
+                Box::pin(Self::upload_image(req, self.mod_cmd.clone(), self.names.clone()))
             }
             (&Method::POST, "/set_white_led") => {
-                return Box::pin(Self::set_white_led(req, self.led_cmd.clone()))
+                Box::pin(Self::set_white_led(req, self.led_cmd.clone()))
+            }
+            (&Method::GET, "/control/ws") => {
+                Box::pin(Self::get_control_ws(req, self.mod_cmd.clone(), self.names.clone()))
+            }
+            (&Method::GET, "/preview") => {
+                Box::pin(Self::get_preview(self.preview.clone()))
+            }
+            (&Method::GET, "/preview/ws") => {
+                Box::pin(Self::get_preview_ws(req, self.preview.clone()))
+            }
+            (&Method::GET, "/config") => {
+                Box::pin(Self::get_state(self.query.clone(), ModularQuery::Config))
+            }
+            (&Method::GET, "/vars") => {
+                Box::pin(Self::get_state(self.query.clone(), ModularQuery::Vars))
+            }
+            (&Method::GET, path) if path.starts_with("/vars/") => {
+                Box::pin(Self::get_var(path.to_string(), self.query.clone()))
+            }
+            (&Method::GET, "/playlist") => {
+                Box::pin(Self::get_playlist(self.playlist.clone()))
+            }
+            (&Method::POST, "/playlist/start") => {
+                Box::pin(Self::send_cmd(self.playlist.clone(), PlaylistMessage::Start))
+            }
+            (&Method::POST, "/playlist/stop") => {
+                Box::pin(Self::send_cmd(self.playlist.clone(), PlaylistMessage::Stop))
+            }
+            (&Method::POST, "/playlist/skip") => {
+                Box::pin(Self::send_cmd(self.playlist.clone(), PlaylistMessage::Skip))
+            }
+            (&Method::GET, "/player") => {
+                Box::pin(Self::get_player(self.player.clone()))
+            }
+            (&Method::POST, "/player/open") => {
+                Box::pin(Self::player_open(req, self.player.clone()))
+            }
+            (&Method::POST, "/player/set") => {
+                Box::pin(Self::player_set(req, self.player.clone()))
+            }
+            (&Method::POST, "/player/play") => {
+                Box::pin(Self::send_cmd(self.player.clone(), PlayerMessage::Play))
+            }
+            (&Method::POST, "/player/pause") => {
+                Box::pin(Self::send_cmd(self.player.clone(), PlayerMessage::Pause))
+            }
+            (&Method::POST, "/player/stop") => {
+                Box::pin(Self::send_cmd(self.player.clone(), PlayerMessage::Stop))
             }
             _ => {
-                return Box::pin(async {mk_status(StatusCode::NOT_FOUND)})
+                Box::pin(async {mk_status(StatusCode::NOT_FOUND)})
             }
-        };
+        }
     }
 }
 
-pub async fn server_run(mod_cmd: Sender<ModularMessage>, led_cmd: Sender<LedMessage>) {
+pub async fn server_run(
+    mod_cmd: Sender<ModularMessage>,
+    led_cmd: Sender<LedMessage>,
+    preview: Option<Sender<PreviewFrame>>,
+    validate: Option<ConfigValidator>,
+    playlist: Option<mpsc::Sender<PlaylistMessage>>,
+    render: Option<RenderLink>,
+    player: Option<mpsc::Sender<PlayerMessage>>,
+) {
     /* HTTP Server initialization */
 
     // We'll bind to 127.0.0.1:3000
@@ -225,9 +734,19 @@ pub async fn server_run(mod_cmd: Sender<ModularMessage>, led_cmd: Sender<LedMess
     let listener = TcpListener::bind(addr).await.unwrap();
 
     println!("Server listening on {addr}");
+    let (query, names) = match render {
+        Some(link) => (Some(link.query), Some(link.names)),
+        None => (None, None),
+    };
     let svc = Svc {
         led_cmd: Arc::new(led_cmd),
-        mod_cmd: Arc::new(mod_cmd)};
+        mod_cmd: Arc::new(mod_cmd),
+        preview: preview.map(Arc::new),
+        validate,
+        playlist: playlist.map(Arc::new),
+        query: query.map(Arc::new),
+        player: player.map(Arc::new),
+        names};
 
     // We start a loop to continuously accept incoming connections
     loop {
@@ -244,7 +763,7 @@ pub async fn server_run(mod_cmd: Sender<ModularMessage>, led_cmd: Sender<LedMess
         tokio::task::spawn(async move {
             // Finally, we bind the incoming connection to our `hello` service
             if let Err(err) = auto::Builder::new(TokioExecutor::new())
-                .serve_connection(io, svc_clone)
+                .serve_connection_with_upgrades(io, svc_clone)
                 .await
             {
                 println!("Error serving connection: {:?}", err);
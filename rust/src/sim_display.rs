@@ -0,0 +1,172 @@
+use std::cell::{Cell, RefCell, RefMut};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::constants;
+use crate::display::{DisplayBackend, WhiteLed};
+
+/*
+ * A display that never touches hardware. Frames are rendered into an
+ * in-memory buffer and copied on flush, so the last complete frame is always
+ * available. Flushes are paced to a fixed frame rate to stand in for the
+ * time the FPGA takes to drain the FIFO.
+ */
+pub struct SimDisplay {
+    /* The buffer being rendered into */
+    fb_cell: RefCell<Vec<u8>>,
+    /* The most recently flushed frame */
+    last_frame: RefCell<Vec<u8>>,
+
+    /* The time one frame takes to "shift out" */
+    frame_period: Duration,
+    last_flush: Cell<Option<Instant>>,
+
+    /* Flush timing statistics */
+    flush_count: Cell<u32>,
+    min_interval: Cell<Duration>,
+    max_interval: Cell<Duration>,
+    total_interval: Cell<Duration>,
+
+    /* The total amount of time spent waiting for the simulated FIFO */
+    wait_time: Cell<Duration>,
+
+    white_led: SimWhiteLed,
+}
+
+/* Stand-in for the white LED register */
+pub struct SimWhiteLed {
+    value: Cell<u32>,
+}
+
+impl SimWhiteLed {
+    pub fn new() -> Self {
+        SimWhiteLed {
+            value: Cell::new(0),
+        }
+    }
+
+    pub fn value(&self) -> u32 {
+        self.value.get()
+    }
+}
+
+impl WhiteLed for SimWhiteLed {
+    fn set_white_led(&self, cold: u8, cool: u8, hot: u8) {
+        // Same packing as the FPGA register
+        let value = (cold as u32) << 8 | (cool as u32) | (hot as u32) << 16;
+        self.value.set(value);
+    }
+}
+
+impl SimDisplay {
+    pub fn new(fps: f32) -> Self {
+        let frame_period = if fps > 0.0 {
+            Duration::from_secs_f32(1.0 / fps)
+        } else {
+            Duration::ZERO
+        };
+
+        SimDisplay {
+            fb_cell: RefCell::new(vec![0u8; constants::FRAME_SIZE_BYTES]),
+            last_frame: RefCell::new(vec![0u8; constants::FRAME_SIZE_BYTES]),
+            frame_period,
+            last_flush: Cell::new(None),
+            flush_count: Cell::new(0),
+            min_interval: Cell::new(Duration::MAX),
+            max_interval: Cell::new(Duration::ZERO),
+            total_interval: Cell::new(Duration::ZERO),
+            wait_time: Cell::new(Duration::ZERO),
+            white_led: SimWhiteLed::new(),
+        }
+    }
+
+    /* Copy of the most recently flushed frame in framebuffer order */
+    pub fn last_frame(&self) -> Vec<u8> {
+        self.last_frame.borrow().clone()
+    }
+
+    pub fn flush_count(&self) -> u32 {
+        self.flush_count.get()
+    }
+
+    /* Returns (min, mean, max) time between consecutive flushes */
+    pub fn flush_intervals(&self) -> Option<(Duration, Duration, Duration)> {
+        let n = self.flush_count.get();
+        if n < 2 {
+            return None;
+        }
+
+        Some((
+            self.min_interval.get(),
+            self.total_interval.get() / (n - 1),
+            self.max_interval.get(),
+        ))
+    }
+
+    pub fn white_led(&self) -> u32 {
+        self.white_led.value()
+    }
+}
+
+impl Drop for SimDisplay {
+    fn drop(&mut self) {
+        if let Some((min, mean, max)) = self.flush_intervals() {
+            println!(
+                "Simulated display: {} flushes, interval min {:?} mean {:?} max {:?}",
+                self.flush_count.get(),
+                min,
+                mean,
+                max
+            );
+        }
+    }
+}
+
+impl WhiteLed for SimDisplay {
+    fn set_white_led(&self, cold: u8, cool: u8, hot: u8) {
+        self.white_led.set_white_led(cold, cool, hot)
+    }
+}
+
+impl DisplayBackend for SimDisplay {
+    fn read_id(&self) -> u16 {
+        0
+    }
+
+    fn empty_count(&self) -> usize {
+        // The simulated FIFO is always drained by the time flush() returns
+        constants::FIFO_DATA_SIZE / 2
+    }
+
+    fn borrow_fb(&self) -> RefMut<'_, [u8]> {
+        RefMut::map(self.fb_cell.borrow_mut(), |v| v.as_mut_slice())
+    }
+
+    fn flush(&self) {
+        let now = Instant::now();
+
+        if let Some(last) = self.last_flush.get() {
+            // Wait for the previous frame to finish shifting out
+            let elapsed = last.elapsed();
+            if elapsed < self.frame_period {
+                sleep(self.frame_period - elapsed);
+            }
+            self.wait_time.set(self.wait_time.get() + now.elapsed());
+
+            let interval = last.elapsed();
+            self.min_interval.set(self.min_interval.get().min(interval));
+            self.max_interval.set(self.max_interval.get().max(interval));
+            self.total_interval.set(self.total_interval.get() + interval);
+        }
+
+        self.last_frame
+            .borrow_mut()
+            .copy_from_slice(&self.fb_cell.borrow());
+        self.last_flush.set(Some(Instant::now()));
+        self.flush_count.set(self.flush_count.get() + 1);
+    }
+
+    fn wait_time(&self) -> Duration {
+        self.wait_time.get()
+    }
+}
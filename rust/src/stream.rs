@@ -0,0 +1,197 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::watch;
+
+use crate::constants;
+
+/* Open Pixel Control command for a list of RGB pixels */
+const OPC_SET_PIXELS: u8 = 0;
+
+const DDP_VERSION_1: u8 = 0x40;
+const DDP_TIMECODE: u8 = 0x10;
+const DDP_REPLY: u8 = 0x04;
+const DDP_QUERY: u8 = 0x02;
+const DDP_PUSH: u8 = 0x01;
+
+/* The latest frame from any stream input, in /set_data order */
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    /* Counts every frame published, so gaps are frames that were never shown */
+    pub seq: u64,
+    pub data: Vec<u8>,
+}
+
+/* Counters shared by the stream inputs and movie_main */
+#[derive(Debug, Default)]
+pub struct StreamStats {
+    /* DDP packets that were numbered by the sender but never arrived */
+    pub lost_packets: AtomicU64,
+    /* Frames replaced by a newer one before they could be shown */
+    pub dropped_frames: AtomicU64,
+}
+
+/*
+ * Where the stream inputs publish complete frames. Only the newest frame is
+ * kept, so a sender that outpaces the display loses frames rather than
+ * building up latency.
+ */
+#[derive(Clone)]
+pub struct FrameSink {
+    tx: Arc<watch::Sender<Frame>>,
+    pub stats: Arc<StreamStats>,
+}
+
+impl FrameSink {
+    pub fn new() -> (Self, watch::Receiver<Frame>) {
+        let (tx, rx) = watch::channel(Frame::default());
+        let sink = FrameSink {
+            tx: Arc::new(tx),
+            stats: Arc::new(StreamStats::default()),
+        };
+        (sink, rx)
+    }
+
+    pub fn publish(&self, data: &[u8]) {
+        self.tx.send_modify(|f| {
+            f.seq += 1;
+            f.data.clear();
+            f.data.extend_from_slice(data);
+        });
+    }
+}
+
+/*
+ * Open Pixel Control over TCP. Each set pixels message on channel 0 (all
+ * channels) or 1 carries RGB triplets for the first pixels of the frame and
+ * is shown as a frame of its own. Pixels it doesn't cover keep their values.
+ */
+pub async fn opc_main(port: u16, sink: FrameSink) {
+    let listener = match TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await {
+        Ok(x) => x,
+        Err(why) => {
+            println!("Couldn't listen for OPC: {why}");
+            return;
+        }
+    };
+    println!("Listening for OPC on port {port}");
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                println!("OPC connection from {addr}");
+                tokio::task::spawn(opc_client(stream, sink.clone()));
+            }
+            Err(why) => println!("OPC accept failure: {why}"),
+        }
+    }
+}
+
+async fn opc_client(mut stream: TcpStream, sink: FrameSink) {
+    let mut frame = vec![0u8; constants::FRAME_SIZE_BYTES];
+    let mut header = [0u8; 4];
+    let mut body = Vec::new();
+
+    loop {
+        // The connection closing is the only way out
+        if stream.read_exact(&mut header).await.is_err() {
+            break;
+        }
+        let [channel, command, hi, lo] = header;
+
+        body.resize(u16::from_be_bytes([hi, lo]) as usize, 0);
+        if stream.read_exact(&mut body).await.is_err() {
+            break;
+        }
+
+        if channel > 1 || command != OPC_SET_PIXELS {
+            continue;
+        }
+
+        let n = body.len().min(frame.len());
+        frame[..n].copy_from_slice(&body[..n]);
+        sink.publish(&frame);
+    }
+}
+
+struct DdpPacket<'a> {
+    /* 1-15, or 0 if the sender doesn't number its packets */
+    seq: u8,
+    push: bool,
+    offset: usize,
+    data: &'a [u8],
+}
+
+/* A DDP data packet. Queries and replies are ignored. */
+fn parse_ddp(b: &[u8]) -> Option<DdpPacket<'_>> {
+    if b.len() < 10 || b[0] & 0xc0 != DDP_VERSION_1 || b[0] & (DDP_QUERY | DDP_REPLY) != 0 {
+        return None;
+    }
+
+    let start = if b[0] & DDP_TIMECODE != 0 { 14 } else { 10 };
+    let offset = u32::from_be_bytes([b[4], b[5], b[6], b[7]]) as usize;
+    let len = u16::from_be_bytes([b[8], b[9]]) as usize;
+    let data = b.get(start..)?;
+
+    Some(DdpPacket {
+        seq: b[1] & 0x0f,
+        push: b[0] & DDP_PUSH != 0,
+        offset,
+        data: &data[..len.min(data.len())],
+    })
+}
+
+/*
+ * DDP over UDP. Packets write into the frame at their byte offset and the
+ * frame is shown when a packet has the push flag set. Gaps in the sequence
+ * numbers are counted as lost packets.
+ */
+pub async fn ddp_main(port: u16, sink: FrameSink) {
+    let socket = match UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port))).await {
+        Ok(x) => x,
+        Err(why) => {
+            println!("Couldn't listen for DDP: {why}");
+            return;
+        }
+    };
+    println!("Listening for DDP on port {port}");
+
+    let mut frame = vec![0u8; constants::FRAME_SIZE_BYTES];
+    let mut buf = [0u8; 2048];
+    let mut last_seq = 0;
+
+    loop {
+        let len = match socket.recv(&mut buf).await {
+            Ok(len) => len,
+            Err(why) => {
+                println!("DDP receive failure: {why}");
+                continue;
+            }
+        };
+
+        let Some(packet) = parse_ddp(&buf[..len]) else {
+            continue;
+        };
+
+        if packet.seq != 0 && last_seq != 0 {
+            let expected = last_seq % 15 + 1;
+            let lost = (packet.seq + 15 - expected) % 15;
+            sink.stats
+                .lost_packets
+                .fetch_add(lost as u64, Ordering::Relaxed);
+        }
+        last_seq = packet.seq;
+
+        if packet.offset < frame.len() {
+            let n = packet.data.len().min(frame.len() - packet.offset);
+            frame[packet.offset..packet.offset + n].copy_from_slice(&packet.data[..n]);
+        }
+
+        if packet.push {
+            sink.publish(&frame);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::config::{ConfigError, ConfigObj};
+use crate::var_types::RealColor;
+
+/* How the blend between the old and new config progresses over time */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Linear,
+    EaseIn,
+    EaseOut,
+    /* Eases in and out (smoothstep) */
+    Smooth,
+}
+
+impl Curve {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Curve::Linear),
+            "ease_in" => Some(Curve::EaseIn),
+            "ease_out" => Some(Curve::EaseOut),
+            "smooth" => Some(Curve::Smooth),
+            _ => None,
+        }
+    }
+
+    /* Maps progress through the transition, 0 to 1, to the new config's weight */
+    pub fn weight(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Curve::Linear => t,
+            Curve::EaseIn => t * t,
+            Curve::EaseOut => t * (2.0 - t),
+            Curve::Smooth => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/*
+ * A crossfade into a new config, given by an optional "transition" member at
+ * the top level of the config, e.g.
+ *   "transition": {"duration": 2.5, "curve": "smooth"}
+ * The duration is in seconds and the curve defaults to linear.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    pub duration: f32,
+    pub curve: Curve,
+}
+
+impl Transition {
+    /* None if the config should replace the running one immediately */
+    pub fn from_config(dict: &ConfigObj) -> Result<Option<Self>, ConfigError> {
+        if dict.opt("transition").is_none() {
+            return Ok(None);
+        }
+
+        let obj = dict.obj("transition")?;
+        let duration = obj.f32("duration")?;
+        if !(duration >= 0.0 && duration.is_finite()) {
+            return Err(obj.err("duration", "expected a non-negative number of seconds"));
+        }
+
+        let curve = match obj.opt("curve") {
+            None => Curve::Linear,
+            Some(_) => {
+                let name = obj.str("curve")?;
+                Curve::from_name(name)
+                    .ok_or_else(|| obj.err("curve", format!("unknown curve '{name}'")))?
+            }
+        };
+
+        Ok((duration > 0.0).then_some(Transition { duration, curve }))
+    }
+}
+
+/* Mixes `old` into `new` in place, where `w` is the weight of `new` */
+pub fn blend(old: &[RealColor], new: &mut [RealColor], w: f32) {
+    for (o, n) in old.iter().zip(new.iter_mut()) {
+        *n = *o * (1.0 - w) + *n * w;
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::config::{elem_path, key_path, ConfigError};
+use crate::output::OutputStage;
+use crate::render_block::{RenderBlock, RenderState, RESERVED_SCALARS};
+use crate::var_types::VarType;
+
+/*
+ * Checks every block input and output, and what the output stage reads,
+ * against the pools declared in the vars stanza. Indices outside a pool are
+ * errors since the getters would panic in the render loop.
+ *
+ * On success, returns warnings for slots that a block reads before a later
+ * block writes them. Those reads see the value left over from the previous
+ * pixel, which is occasionally intentional but usually an ordering mistake.
+ */
+pub fn validate_blocks(
+    state: &RenderState,
+    blocks: &[Box<dyn RenderBlock>],
+    output: &OutputStage,
+    path: &str,
+) -> Result<Vec<ConfigError>, ConfigError> {
+    // Slot 0 of either pool is the output
+    if state.pool_len(output.source()) == 0 {
+        return Err(ConfigError::new(
+            &key_path("vars", output.source().name()),
+            "an output color is required",
+        ));
+    }
+    if let Some(idx) = output.brightness_idx() {
+        let len = state.pool_len(VarType::Scalar);
+        if idx >= len {
+            return Err(ConfigError::new(
+                &key_path("output", "brightness"),
+                format!(
+                    "{} index {} is out of range ({} defined)",
+                    VarType::Scalar.name(),
+                    idx,
+                    len
+                ),
+            ));
+        }
+    }
+
+    // Range checks
+    for (i, block) in blocks.iter().enumerate() {
+        let block_path = elem_path(path, i);
+        let ports = block
+            .inputs()
+            .into_iter()
+            .map(|p| ("inputs", p))
+            .chain(block.outputs().into_iter().map(|p| ("outputs", p)));
+
+        for (dir, port) in ports {
+            let len = state.pool_len(port.ty);
+            if port.index >= len {
+                return Err(ConfigError::new(
+                    &key_path(&key_path(&block_path, dir), &port.name),
+                    format!(
+                        "{} index {} is out of range ({} defined)",
+                        port.ty.name(),
+                        port.index,
+                        len
+                    ),
+                ));
+            }
+        }
+    }
+
+    // The first block to write each slot
+    let mut first_writer = HashMap::<(VarType, usize), usize>::new();
+    for (i, block) in blocks.iter().enumerate() {
+        for port in block.outputs() {
+            first_writer.entry((port.ty, port.index)).or_insert(i);
+        }
+    }
+
+    // Slots written by the render loop are always fresh
+    let mut written: HashSet<(VarType, usize)> = (0..RESERVED_SCALARS)
+        .chain(state.clock_slots().into_iter().flatten())
+        .map(|i| (VarType::Scalar, i))
+        .collect();
+
+    let mut warnings = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let block_path = elem_path(path, i);
+        for port in block.inputs() {
+            let slot = (port.ty, port.index);
+            if written.contains(&slot) {
+                continue;
+            }
+            if let Some(writer) = first_writer.get(&slot) {
+                if *writer >= i {
+                    warnings.push(ConfigError::new(
+                        &key_path(&key_path(&block_path, "inputs"), &port.name),
+                        format!(
+                            "{} {} is read before {} writes it",
+                            port.ty.name(),
+                            port.index,
+                            elem_path(path, *writer)
+                        ),
+                    ));
+                }
+            }
+        }
+
+        for port in block.outputs() {
+            written.insert((port.ty, port.index));
+        }
+    }
+
+    Ok(warnings)
+}
@@ -1,15 +1,104 @@
 use json::JsonValue;
 use num_traits::clamp;
+use std::collections::HashMap;
 use std::ops::{Add, Mul};
 use base64::prelude::*;
 
-pub trait FromJson {
-    fn from_obj(v: &JsonValue) -> Self;
+use crate::config::{ConfigError, ConfigObj};
+
+/* The variable pools in RenderState */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VarType {
+    Scalar,
+    Position,
+    Color,
+    RColor,
+    Data,
+    Gradient,
+}
+
+impl VarType {
+    pub const ALL: [VarType; 6] = [
+        VarType::Scalar,
+        VarType::Position,
+        VarType::Color,
+        VarType::RColor,
+        VarType::Data,
+        VarType::Gradient,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|ty| ty.name() == name)
+    }
+
+    /* The name of the pool in the vars stanza */
+    pub fn name(self) -> &'static str {
+        match self {
+            VarType::Scalar => "float",
+            VarType::Position => "position",
+            VarType::Color => "color",
+            VarType::RColor => "rcolor",
+            VarType::Data => "data",
+            VarType::Gradient => "gradient",
+        }
+    }
+}
+
+/*
+ * Maps variable names from the vars stanza to their index in each pool, and
+ * records how many slots each pool has so that indices can be checked too.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VarNames {
+    names: HashMap<(VarType, String), usize>,
+    lens: HashMap<VarType, usize>,
+}
+
+impl VarNames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* Returns false if the name is already used in this pool */
+    pub fn insert(&mut self, ty: VarType, name: &str, index: usize) -> bool {
+        self.names.insert((ty, name.to_string()), index).is_none()
+    }
+
+    pub fn get(&self, ty: VarType, name: &str) -> Option<usize> {
+        self.names.get(&(ty, name.to_string())).copied()
+    }
+
+    pub fn set_len(&mut self, ty: VarType, len: usize) {
+        self.lens.insert(ty, len);
+    }
+
+    /* The number of slots in a pool, 0 for a pool the config doesn't have */
+    pub fn len(&self, ty: VarType) -> usize {
+        self.lens.get(&ty).copied().unwrap_or(0)
+    }
+
+    /* The reverse of get(), for reporting state back */
+    pub fn name_of(&self, ty: VarType, index: usize) -> Option<&str> {
+        self.names
+            .iter()
+            .find(|((t, _), i)| *t == ty && **i == index)
+            .map(|((_, name), _)| name.as_str())
+    }
+}
+
+/* `path` locates the value within its document for error reporting */
+pub trait FromJson: Sized {
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError>;
+}
+
+/* The inverse of FromJson, producing the same form that from_obj() accepts */
+pub trait ToJson {
+    fn to_json(&self) -> JsonValue;
 }
 
 impl FromJson for f32 {
-    fn from_obj(v: &JsonValue) -> Self {
-        v.as_f32().expect("Value must be a float")
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        v.as_f32().ok_or_else(|| ConfigError::new(path, "expected a number"))
     }
 }
 
@@ -19,25 +108,27 @@ pub struct Position {
     pub y: f32,
 }
 
+impl ToJson for f32 {
+    fn to_json(&self) -> JsonValue {
+        // Widening directly would print 0.1 as 0.10000000149011612
+        self.to_string().parse::<f64>().map_or(JsonValue::Null, JsonValue::from)
+    }
+}
+
 impl FromJson for Position {
-    fn from_obj(v: &JsonValue) -> Self {
-        let dict = match v {
-            JsonValue::Object(ref x) => x,
-            _ => panic!("Position is not an object"),
-        };
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        let dict = ConfigObj::new(v, path)?;
+
+        let x = dict.f32("x")?;
+        let y = dict.f32("y")?;
 
-        let x = dict
-            .get("x")
-            .expect("No x parameter in Position")
-            .as_f32()
-            .expect("Failed to interpret x parameter");
-        let y = dict
-            .get("y")
-            .expect("No y parameter in Position")
-            .as_f32()
-            .expect("Failed to interpret y parameter");
+        Ok(Position { x, y })
+    }
+}
 
-        Position { x, y }
+impl ToJson for Position {
+    fn to_json(&self) -> JsonValue {
+        json::object! {x: self.x.to_json(), y: self.y.to_json()}
     }
 }
 
@@ -73,29 +164,25 @@ pub struct Color {
 }
 
 impl FromJson for Color {
-    fn from_obj(v: &JsonValue) -> Self {
-        let dict = match v {
-            JsonValue::Object(ref x) => x,
-            _ => panic!("Color is not an object"),
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        let dict = ConfigObj::new(v, path)?;
+
+        let channel = |key: &str| {
+            dict.value(key)?
+                .as_u8()
+                .ok_or_else(|| dict.err(key, "expected an integer in [0, 255]"))
         };
+        let r = channel("r")?;
+        let g = channel("g")?;
+        let b = channel("b")?;
 
-        let r = dict
-            .get("r")
-            .expect("No r parameter in Color")
-            .as_u8()
-            .expect("Failed to interpret r parameter");
-        let g = dict
-            .get("g")
-            .expect("No g parameter in Color")
-            .as_u8()
-            .expect("Failed to interpret g parameter");
-        let b = dict
-            .get("b")
-            .expect("No b parameter in Color")
-            .as_u8()
-            .expect("Failed to interpret b parameter");
+        Ok(Color { r, g, b })
+    }
+}
 
-        Color { r, g, b }
+impl ToJson for Color {
+    fn to_json(&self) -> JsonValue {
+        json::object! {r: self.r, g: self.g, b: self.b}
     }
 }
 
@@ -147,29 +234,20 @@ pub struct RealColor {
 }
 
 impl FromJson for RealColor {
-    fn from_obj(v: &JsonValue) -> Self {
-        let dict = match v {
-            JsonValue::Object(ref x) => x,
-            _ => panic!("RealColor is not an object"),
-        };
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
+        let dict = ConfigObj::new(v, path)?;
 
-        let r = dict
-            .get("r")
-            .expect("No r parameter in Color")
-            .as_f32()
-            .expect("Failed to interpret r parameter");
-        let g = dict
-            .get("g")
-            .expect("No g parameter in Color")
-            .as_f32()
-            .expect("Failed to interpret g parameter");
-        let b = dict
-            .get("b")
-            .expect("No b parameter in Color")
-            .as_f32()
-            .expect("Failed to interpret b parameter");
+        let r = dict.f32("r")?;
+        let g = dict.f32("g")?;
+        let b = dict.f32("b")?;
+
+        Ok(Self { r, g, b })
+    }
+}
 
-        Self { r, g, b }
+impl ToJson for RealColor {
+    fn to_json(&self) -> JsonValue {
+        json::object! {r: self.r.to_json(), g: self.g.to_json(), b: self.b.to_json()}
     }
 }
 
@@ -200,16 +278,23 @@ impl Mul<f32> for RealColor {
 pub type Data = Vec<u8>;
 
 impl FromJson for Data {
-    fn from_obj(v: &JsonValue) -> Self {
+    fn from_obj(v: &JsonValue, path: &str) -> Result<Self, ConfigError> {
         /*
          * The documentation for JsonValue seems to indicate that
          * as_str() will not return a value for anything but Short
          * and String.
          */
-        let b64_str = match v.as_str() {
-            Some(x) => x,
-            _ => panic!("Data is not a string: '{:?}'", v),
-        };
-        BASE64_STANDARD.decode(b64_str).expect("Failed to decode base64")
+        let b64_str = v
+            .as_str()
+            .ok_or_else(|| ConfigError::new(path, "expected a base64 string"))?;
+        BASE64_STANDARD
+            .decode(b64_str)
+            .map_err(|why| ConfigError::new(path, format!("invalid base64: {why}")))
     }
-}
\ No newline at end of file
+}
+
+impl ToJson for Data {
+    fn to_json(&self) -> JsonValue {
+        BASE64_STANDARD.encode(self).into()
+    }
+}
@@ -0,0 +1,79 @@
+#!/usr/bin/env python3
+
+# Sends Art-Net or sACN (E1.31) to the ceiling, for testing the DMX inputs.
+#
+#   dmx_send.py --proto artnet pixels        moving rainbow for movie_ceiling --dmx
+#   dmx_send.py --proto sacn levels 1=255 3=128    channel levels for --dmx-map
+
+import argparse
+import colorsys
+import socket
+import struct
+import time
+import uuid
+
+LED_COUNT = 118
+STRING_COUNT = 46
+PIXELS_PER_UNIVERSE = 170
+
+CID = uuid.uuid4().bytes
+
+
+def artnet_packet(universe, seq, data):
+    return (b"Art-Net\0" + struct.pack("<H", 0x5000) + struct.pack(">H", 14)
+            + bytes([seq, 0]) + struct.pack("<H", universe)
+            + struct.pack(">H", len(data)) + data)
+
+
+def sacn_packet(universe, seq, data):
+    dmp = struct.pack(">HBBHHH", 0x7000 | (10 + len(data) + 1), 0x02, 0xa1, 0, 1, len(data) + 1) + b"\0" + data
+    framing = (struct.pack(">HI", 0x7000 | (77 + len(dmp)), 0x02) + b"dmx_send.py".ljust(64, b"\0")
+               + struct.pack(">BHBBH", 100, 0, seq, 0, universe) + dmp)
+    root = (struct.pack(">HH", 0x0010, 0) + b"ASC-E1.17\0\0\0"
+            + struct.pack(">HI", 0x7000 | (22 + len(framing)), 0x04) + CID + framing)
+    return root
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument("--host", default="127.0.0.1")
+    parser.add_argument("--proto", choices=["artnet", "sacn"], default="artnet")
+    parser.add_argument("--universe", type=int, default=1)
+    parser.add_argument("--fps", type=float, default=30.0)
+    sub = parser.add_subparsers(dest="mode", required=True)
+    sub.add_parser("pixels")
+    levels = sub.add_parser("levels")
+    levels.add_argument("levels", nargs="+", help="channel=level, channels counting from 1")
+    args = parser.parse_args()
+
+    make = artnet_packet if args.proto == "artnet" else sacn_packet
+    port = 6454 if args.proto == "artnet" else 5568
+    sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+
+    if args.mode == "levels":
+        data = bytearray(512)
+        for kv in args.levels:
+            ch, level = kv.split("=")
+            data[int(ch) - 1] = int(level)
+        sock.sendto(make(args.universe, 0, bytes(data)), (args.host, port))
+        return
+
+    seq = 0
+    while True:
+        t = time.time()
+        frame = bytearray()
+        for y in range(STRING_COUNT):
+            for x in range(LED_COUNT):
+                r, g, b = colorsys.hsv_to_rgb((x / LED_COUNT + t * 0.2) % 1.0, 1.0, 0.3)
+                frame.extend([int(r * 255), int(g * 255), int(b * 255)])
+
+        step = PIXELS_PER_UNIVERSE * 3
+        for i, start in enumerate(range(0, len(frame), step)):
+            sock.sendto(make(args.universe + i, seq, bytes(frame[start:start + step])), (args.host, port))
+
+        seq = (seq + 1) % 256
+        time.sleep(1.0 / args.fps)
+
+
+if __name__ == "__main__":
+    main()
@@ -3,14 +3,13 @@
 import mido
 import pprint
 import logging
-import requests
 import json
 
-logging.basicConfig(level=logging.INFO)
+from websockets.sync.client import connect
 
-session = requests.Session()
+logging.basicConfig(level=logging.INFO)
 
-FB_URI = "http://beaglebone:3000/set_scalar"
+FB_URI = "ws://beaglebone:3000/control/ws"
 NOTE_BASE = 36
 CC_BASE = 36
 
@@ -18,13 +17,22 @@ cur_cc_base = CC_BASE
 scale = 1.0
 bipolar = False
 
-def set_scalar(session, idx, value):
+def drain(ws):
+    # Acks and events aren't used here, but don't let them pile up
+    try:
+        while True:
+            ws.recv(timeout=0)
+    except TimeoutError:
+        pass
+
+def set_scalar(ws, idx, value):
     if idx < 0:
         print(f"Bad scalar index {idx}")
         return
 
     print(f"set scalar {idx} = {value}")
-    session.post(FB_URI, json.dumps({"index": idx, "value": value}))
+    ws.send(json.dumps({"set": "scalar", "index": idx, "value": value}))
+    drain(ws)
 
 def get_value(raw, scale, bipolar):
     if bipolar:
@@ -35,7 +43,7 @@ def get_value(raw, scale, bipolar):
         return scale * (msg.value) / 127.0
 
 
-with mido.open_input("LPD8 mk2 0") as port:
+with mido.open_input("LPD8 mk2 0") as port, connect(FB_URI) as ws:
     for msg in port:
         match msg.type:
             case "note_on":
@@ -64,7 +72,7 @@ with mido.open_input("LPD8 mk2 0") as port:
             case "control_change":
                 idx = msg.control - cur_cc_base
                 val = get_value(msg.value, scale, bipolar)
-                set_scalar(session, idx, val)
+                set_scalar(ws, idx, val)
             case _:
                 # Ignore other messages
                 pass
\ No newline at end of file
@@ -0,0 +1,71 @@
+#!/usr/bin/env python3
+
+# Streams a moving rainbow to movie_ceiling over Open Pixel Control or DDP.
+#
+#   stream_send.py opc          needs movie_ceiling --opc
+#   stream_send.py ddp          needs movie_ceiling --ddp
+
+import argparse
+import colorsys
+import socket
+import struct
+import time
+
+LED_COUNT = 118
+STRING_COUNT = 46
+
+DDP_PORT = 4048
+OPC_PORT = 7890
+# Keeps each DDP packet inside a standard Ethernet MTU
+DDP_CHUNK = 1440
+
+
+def rainbow(t):
+    frame = bytearray()
+    for y in range(STRING_COUNT):
+        for x in range(LED_COUNT):
+            r, g, b = colorsys.hsv_to_rgb((x / LED_COUNT + t * 0.2) % 1.0, 1.0, 0.3)
+            frame.extend([int(r * 255), int(g * 255), int(b * 255)])
+    return bytes(frame)
+
+
+def send_opc(sock, frame):
+    sock.sendall(struct.pack(">BBH", 0, 0, len(frame)) + frame)
+
+
+def send_ddp(sock, addr, frame, seq):
+    for offset in range(0, len(frame), DDP_CHUNK):
+        chunk = frame[offset:offset + DDP_CHUNK]
+        last = offset + DDP_CHUNK >= len(frame)
+        flags = 0x40 | (0x01 if last else 0)
+        sock.sendto(struct.pack(">BBBBIH", flags, seq, 0x0b, 1, offset, len(chunk)) + chunk, addr)
+        seq = seq % 15 + 1
+    return seq
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument("proto", choices=["opc", "ddp"])
+    parser.add_argument("--host", default="127.0.0.1")
+    parser.add_argument("--port", type=int)
+    parser.add_argument("--fps", type=float, default=60.0)
+    args = parser.parse_args()
+
+    if args.proto == "opc":
+        sock = socket.create_connection((args.host, args.port or OPC_PORT))
+    else:
+        sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        addr = (args.host, args.port or DDP_PORT)
+
+    seq = 1
+    while True:
+        frame = rainbow(time.time())
+        if args.proto == "opc":
+            send_opc(sock, frame)
+        else:
+            seq = send_ddp(sock, addr, frame, seq)
+        time.sleep(1.0 / args.fps)
+
+
+if __name__ == "__main__":
+    main()